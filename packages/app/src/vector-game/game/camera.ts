@@ -6,6 +6,8 @@ import {
   TextureType,
   WasmFloat32Array,
   WasmInt32Array,
+  WasmMapLayers,
+  WasmPickBuffer,
   WasmStripePerCoordMap,
   WasmTextureMap,
   WasmTextureMetaMap,
@@ -28,8 +30,10 @@ export class Camera {
   public ctx: CanvasRenderingContext2D;
   public width: number;
   public height: number;
-  public range: number;
+  public viewDistance: number; // in cells, things fade into the sky before it
   public lightRange: number;
+  public mirrorBounces: number; // reflections of reflections, 0 draws mirrors as walls
+  public mirrorTint: number; // RGBA, alpha is how much each reflection is tinted
   public scale: number;
   public canvas: HTMLCanvasElement;
   public map: GridMap;
@@ -39,11 +43,14 @@ export class Camera {
   public skyTextureRef: WasmUint8Array;
   public zBufferRef: WasmFloat32Array;
   public mapRef: WasmUInt64Array;
+  public materialRef: WasmUInt64Array;
+  public layers: WasmMapLayers; // one per storey, this map only has the ground floor
   public initialized: boolean;
   public spriteHashMap: WasmStripePerCoordMap; // sprites per coordinate
   public spriteTextureHashMap: WasmTextureMap;
   public backgroundRef: BackgroundImageWasm;
   public spriteTextureMetaHashMap: WasmTextureMetaMap;
+  public pickBuffer: WasmPickBuffer; // what the last frame drew where
 
   constructor(canvas: HTMLCanvasElement, map: GridMap, spriteMap: SpriteMap) {
     this.ctx = canvas.getContext("2d", { alpha: false });
@@ -52,8 +59,10 @@ export class Camera {
     this.width = this.width + 4 - (this.width % 4);
     this.height = this.height + 4 - (this.height % 4);
 
-    this.range = 40;
+    this.viewDistance = 40;
     this.lightRange = 15;
+    this.mirrorBounces = 2;
+    this.mirrorTint = 0x8899aa30;
     this.scale = (this.width + this.height) / 1200;
     this.map = map;
 
@@ -78,8 +87,13 @@ export class Camera {
 
     this.zBufferRef = new WasmFloat32Array(this.width);
 
-    this.mapRef = new WasmUInt64Array(map.size * map.size);
+    this.mapRef = new WasmUInt64Array(map.width * map.height);
     this.mapRef.set(map.wallGrid);
+    this.materialRef = new WasmUInt64Array(map.width * map.height);
+    this.materialRef.set(map.materialGrid);
+    this.layers = new WasmMapLayers(map.width, map.height);
+    this.layers.addLayer(this.mapRef.ptr, this.materialRef.ptr, 0);
+    this.pickBuffer = new WasmPickBuffer();
 
     const allSprites = new Float32Array(
       flatten(
@@ -181,33 +195,32 @@ export class Camera {
     }
 
     render(
-      player.position.x,
-      player.position.y,
-      player.position.dir_x,
-      player.position.dir_y,
-      player.position.plane_x,
-      player.position.plane_y,
-      player.position.pitch,
-      player.position.z,
-      player.position.plane_y_initial,
+      player.position,
       this.ceilingFloorPixelsRef.ptr,
       this.zBufferRef.ptr,
-      this.mapRef.ptr,
-      this.map.size,
+      this.layers,
       this.width,
       this.height,
       this.lightRange,
-      this.range,
+      this.viewDistance,
       this.map.light,
+      this.mirrorBounces,
+      this.mirrorTint,
       this.backgroundRef,
       this.spriteHashMap,
       this.spriteTextureHashMap,
-      this.spriteTextureMetaHashMap
+      this.spriteTextureMetaHashMap,
+      this.pickBuffer
     );
 
     this.drawWeapon(player.weapon, player.paces);
   }
 
+  // the wall, door, window or sprite at a pixel of the last frame, e.g. under the crosshair
+  pick(x: number, y: number) {
+    return this.pickBuffer.pick(x, y, this.layers);
+  }
+
   drawCanvas() {
     // this.imageData.data.set(this.ceilingFloorPixelsRef.buffer);
 
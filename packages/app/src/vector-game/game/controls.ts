@@ -9,6 +9,9 @@ export interface ControlStates {
   lookDown: boolean;
   jumpUp: boolean;
   jumpDown: boolean;
+  use: boolean;
+  zoomIn: boolean;
+  zoomOut: boolean;
 }
 
 export class Controls {
@@ -21,6 +24,9 @@ export class Controls {
     83: "lookDown", // s
     68: "jumpUp", // d
     70: "jumpDown", // f
+    32: "use", // space, opens doors
+    90: "zoomIn", // z
+    88: "zoomOut", // x
   };
   public states: ControlStates = {
     left: false,
@@ -31,6 +37,9 @@ export class Controls {
     lookDown: false,
     jumpUp: false,
     jumpDown: false,
+    use: false,
+    zoomIn: false,
+    zoomOut: false,
   };
 
   constructor() {
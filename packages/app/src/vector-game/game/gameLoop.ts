@@ -19,7 +19,7 @@ export class GameLoop {
   public frameTime: number;
 
   constructor() {
-    this.map = new GridMap(12);
+    this.map = new GridMap(13, 13);
     this.spriteMap = new SpriteMap();
     this.display = document.getElementById("display") as HTMLCanvasElement;
     this.controls = new Controls();
@@ -68,14 +68,15 @@ export class GameLoop {
 
   loop() {
     // this.map.update(this.frameTime);
+    this.camera.layers.updateDoors(this.frameTime);
+    this.camera.layers.updatePushWalls(this.frameTime);
     this.player.update(this.controls.states, this.map, this.frameTime);
     this.camera.render(this.player);
     this.camera.drawCanvas();
   }
 
   findSpawnPoint() {
-    // return new Player(x + 0.5, y + 0.5, -1, 0, 0, 0.66); // original
-    // return new Player(4, 4, 0, -1, 0, 0, 1.1, this.camera); // looking east
-    return new Player(4, 4, 0, 0, -1, -1.1, 0, this.camera); // looking north
+    // return new Player(4, 4, 0.5, 0, 80, this.camera); // looking east
+    return new Player(4, 4, 0.5, -Math.PI / 2, 80, this.camera); // looking north
   }
 }
@@ -37,8 +37,10 @@ export interface Point {
 }
 
 export class GridMap {
-  public size: number;
+  public width: number;
+  public height: number;
   public wallGrid: BigUint64Array;
+  public materialGrid: BigUint64Array; // per cell textures, see `CellMaterial` in src/cell.rs
   public skybox: Bitmap;
   public wallTexture: Bitmap;
   public treeTexture: Bitmap;
@@ -54,9 +56,10 @@ export class GridMap {
   public ladyTextures: Bitmap[];
   public light: number;
 
-  constructor(size: number) {
-    this.size = size;
-    this.wallGrid = new BigUint64Array(size * size);
+  constructor(width: number, height: number) {
+    this.width = width;
+    this.height = height;
+    this.wallGrid = new BigUint64Array(width * height);
     this.skybox = new Bitmap(panorama, 2000, 750);
     this.windowTexture = new Bitmap(windowTexture, 1024, 1024);
     this.wallTexture = new Bitmap(wallTexture, 1024, 1024);
@@ -81,7 +84,8 @@ export class GridMap {
     ];
     this.light = 0;
 
-    // 64 bits, 8 bytes
+    // 64 bits, 8 bytes; the layout is defined by `Cell` in src/cell.rs,
+    // use `Cell.fromU64(value)` / `cell.toU64()` instead of hand-writing hex
     //     offset_secondary3  width3     thickness3 offset_primary3    offset_secondary2  width2     thickness2 offset_primary2    offset_secondary  width     thickness offset_primary   reserve1 reserve2 num_walls1 num_walls0 reserve5 reserve6 reserve7 is_window   north2  north  door  door23   road   north3 ceiling&floor  wall
     // BIN 0000               0000       0000       0000               0000               0000       0000       0000               0000              0000      0000      0000             0        0        0          0          0        0        0        0           0       0      0     0        0      0       0              0
 
@@ -119,7 +123,9 @@ export class GridMap {
       /* 12 */ 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008, 0x0000000000000008,
       /* 13 */ 0x0000000000000008, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000, 0x0000000000000000,
     ].map(val=>BigInt(val)));
-    this.size = Math.sqrt(this.wallGrid.length);
+    this.height = this.wallGrid.length / this.width;
+    // 0 everywhere, so every segment uses the default texture for its kind
+    this.materialGrid = new BigUint64Array(this.width * this.height);
 
     makeAutoObservable(this);
   }
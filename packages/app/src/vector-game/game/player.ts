@@ -1,52 +1,31 @@
 import { makeAutoObservable } from "mobx";
-import { rotate_view, walk } from "../../../wasm";
+import {
+  castRay,
+  HitKind,
+  Push,
+  Camera as WasmCamera,
+  walk,
+} from "../../../wasm";
 import knifeHand from "../../assets/knife_hand.png";
 import { Bitmap } from "./bitmap";
 import { Camera } from "./camera";
 import { ControlStates } from "./controls";
 import { GridMap } from "./gridMap";
-export interface Position {
-  x: number; // pos x of player
-  y: number; // pos y of player
-  z: number; // pos z of player
-  dir_x: number; // x component of direction vector
-  dir_y: number; // y component of direction vector
-  plane_x: number; // x component of camera plane
-  plane_y: number; // y component of camera plane
-  pitch: number;
-  plane_y_initial: number;
-}
-
 export class Player {
   public weapon: Bitmap;
   public paces: number;
-  public position: Position;
+  public position: WasmCamera; // position, yaw, field of view, pitch and z
   public camera: Camera;
 
   constructor(
     x: number,
     y: number,
-    z: number,
-    dir_x: number,
-    dir_y: number,
-    plane_x: number,
-    plane_y: number,
+    z: number, // eye height in wall heights, 0.5 standing
+    yaw: number, // radians, 0 looking east
+    fov: number, // degrees
     camera: Camera
   ) {
-    this.position = {
-      x,
-      y,
-      z,
-      dir_x,
-      dir_y,
-      plane_x,
-      plane_y,
-      pitch: 0,
-      plane_y_initial:
-        Math.abs(plane_y) > Math.abs(plane_x)
-          ? Math.abs(plane_y)
-          : Math.abs(plane_x), // basically direction vector length; TODO
-    };
+    this.position = new WasmCamera(x, y, yaw, fov, 0, z);
     this.weapon = new Bitmap(knifeHand, 319, 320);
     this.paces = 0;
     this.camera = camera;
@@ -55,51 +34,61 @@ export class Player {
   }
 
   public rotate = (frameTime: number, multiplier: number) => {
-    const [dir_x, dir_y, plane_x, plane_y] = rotate_view(
-      frameTime,
-      multiplier,
-      this.position.dir_x,
-      this.position.dir_y,
-      this.position.plane_x,
-      this.position.plane_y
-    );
-    this.position.dir_x = dir_x;
-    this.position.dir_y = dir_y;
-    this.position.plane_x = plane_x;
-    this.position.plane_y = plane_y;
+    this.position.turn(0.8 * Math.PI * frameTime * multiplier);
+  };
+
+  // narrows the field of view to zoom in, widens it to zoom out
+  public zoom = (frameTime: number, multiplier: number) => {
+    this.position.fov -= 60 * frameTime * multiplier;
   };
 
-  // move if no wall in front of you
+  // move if no wall in front of you, coming out of the other end of portals turned
   public walk = (distance: number, map: GridMap) => {
-    const [x, y] = walk(
-      this.position.x,
-      this.position.y,
-      this.position.dir_x,
-      this.position.dir_y,
-      this.position.plane_x,
-      this.position.plane_y,
-      this.position.pitch,
-      this.position.z,
-      this.position.plane_y_initial,
+    walk(
+      this.position,
       distance,
-      this.camera.mapRef.ptr,
-      map.size,
+      this.camera.layers,
       this.camera.width,
-      this.camera.range,
+      this.camera.height,
+      this.camera.viewDistance,
       map.wallTexture.width
     );
-    this.position.x = x;
-    this.position.y = y;
   };
 
+  // open the door or push the wall straight ahead, if it's within reach
+  public use = () => {
+    const hit = castRay(
+      this.position.x,
+      this.position.y,
+      this.position.dirX,
+      this.position.dirY,
+      this.position.z,
+      this.camera.layers,
+      1.5
+    );
+    const layer = this.camera.layers.layerAt(this.position.feet);
+    if (hit?.kind === HitKind.Door) {
+      this.camera.layers.openDoor(layer, hit.map_x, hit.map_y);
+    } else if (hit?.kind === HitKind.Wall && hit.segment === undefined) {
+      // away from us, two cells deep; only solid cells with room behind them move
+      this.camera.layers.pushWall(
+        layer,
+        hit.map_x,
+        hit.map_y,
+        new Push(-Math.round(hit.normal_x), -Math.round(hit.normal_y), 2, 1)
+      );
+    }
+  };
+
+  // eye height is in wall heights, 0.5 standing
   public jumpUp = (frameTime: number) => {
-    this.position.z += 400 * frameTime;
-    if (this.position.z > 300) this.position.z = 300;
+    this.position.z += 0.5 * frameTime;
+    if (this.position.z > 0.85) this.position.z = 0.85;
   };
 
   public jumpDown = (frameTime: number) => {
-    this.position.z -= 400 * frameTime;
-    if (this.position.z < 0) this.position.z = 0;
+    this.position.z -= 0.5 * frameTime;
+    if (this.position.z < 0.2) this.position.z = 0.2; // crouching
   };
 
   public lookDown = (frameTime: number) => {
@@ -125,6 +114,11 @@ export class Player {
     if (controls.forward) this.walk(3 * frameTime, map);
     else if (controls.backward) this.walk(-3 * frameTime, map);
 
+    if (controls.use) this.use();
+
+    if (controls.zoomIn) this.zoom(frameTime, 1);
+    else if (controls.zoomOut) this.zoom(frameTime, -1);
+
     if (controls.jumpDown) this.jumpDown(frameTime);
     else if (controls.jumpUp) this.jumpUp(frameTime);
 
@@ -140,7 +134,10 @@ export class Player {
         Math.min(0, this.position.pitch + 100 * frameTime)
       );
 
-    if (this.position.z > 0)
-      this.position.z = Math.max(0, this.position.z - 100 * frameTime);
+    // back to standing height
+    if (this.position.z > 0.5)
+      this.position.z = Math.max(0.5, this.position.z - 0.1 * frameTime);
+    else if (this.position.z < 0.5)
+      this.position.z = Math.min(0.5, this.position.z + 0.1 * frameTime);
   };
 }
@@ -8,23 +8,23 @@ const Map = () => {
   const gameContext = useContext(GameContext);
 
   const map = gameContext.gameLoop?.map?.wallGrid;
-  const size = gameContext.gameLoop?.map?.size;
+  const width = gameContext.gameLoop?.map?.width;
   const playerPosition = gameContext.gameLoop?.player?.position;
   const fps = gameContext.gameLoop?.fps;
   const minFps = gameContext.gameLoop?.getMinFPS();
 
-  if (!map || !playerPosition || !size) {
+  if (!map || !playerPosition || !width) {
     return <></>;
   }
   return (
     <div
       className="absolute bottom-0 left-0 min-w-[100px]"
-      style={{ width: size * 4 }}
+      style={{ width: width * 4 }}
     >
       <div className="text-white">{fps} FPS</div>
       <div className="text-white">{minFps} min FPS</div>
-      <MapPerson playerPosition={{ ...playerPosition }} size={size} />
-      <MapDetail map={map} size={size} />
+      <MapPerson playerPosition={{ ...playerPosition }} size={width} />
+      <MapDetail map={map} width={width} />
     </div>
   );
 };
@@ -2,12 +2,12 @@ import { memo } from "react";
 
 interface MapDetailProps {
   map: BigUint64Array;
-  size: number;
+  width: number;
 }
 
 const MapDetail = (props: MapDetailProps) => {
   return (
-    <div className="flex flex-row flex-wrap" style={{ width: props.size * 4 }}>
+    <div className="flex flex-row flex-wrap" style={{ width: props.width * 4 }}>
       {[...props.map].map((pix, idx) => {
         return (
           <div
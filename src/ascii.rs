@@ -0,0 +1,302 @@
+//! ASCII-art maps, for tests and bug reports that would otherwise need hand-encoded cells.
+//!
+//! ```text
+//! #####
+//! #.a.#
+//! #-D|#
+//! #=W=#
+//! #####
+//!
+//! a = 7
+//! ```
+//!
+//! One character per cell:
+//!
+//! ```text
+//! #  solid wall
+//! |  thin wall on the east edge
+//! -  thin wall on the north edge
+//! D  door, on the north edge if there's a wall west or east of it, else on the east edge
+//! W  window, placed like a door
+//! .  floor, under a ceiling
+//! =  road
+//! a  any other letter is a sprite, in the middle of a floor cell
+//! ```
+//!
+//! Thin walls, doors and windows stand on floor under a ceiling too, like sprites.
+//!
+//! The grid may be indented, all rows must be equally wide. After a blank line, lines like
+//! `a = 7` give the texture id of the sprites of a letter, which `Level::from_ascii` needs.
+
+use wasm_bindgen::prelude::*;
+
+use crate::cell::{Cell, WallSegment};
+use crate::grid::MapGrid;
+use crate::level::{Level, LevelError};
+
+const SOLID: char = '#';
+const EAST_WALL: char = '|';
+const NORTH_WALL: char = '-';
+const DOOR: char = 'D';
+const WINDOW: char = 'W';
+const FLOOR: char = '.';
+const ROAD: char = '=';
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsciiSprite {
+    pub symbol: char,
+    pub x: f32,
+    pub y: f32,
+    pub r#type: Option<i32>, // from the legend below the grid
+}
+
+pub struct AsciiMap {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<u64>,
+    pub sprites: Vec<AsciiSprite>,
+}
+
+impl AsciiMap {
+    pub fn parse(text: &str) -> Result<AsciiMap, LevelError> {
+        let lines: Vec<(usize, &str)> = text
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line.trim_end()))
+            .skip_while(|(_, line)| line.is_empty())
+            .collect();
+        let rows: Vec<(usize, &str)> = lines
+            .iter()
+            .take_while(|(_, line)| !line.is_empty())
+            .copied()
+            .collect();
+        let indent = rows
+            .iter()
+            .map(|(_, line)| line.len() - line.trim_start().len())
+            .min()
+            .unwrap_or(0);
+
+        let symbols: Vec<Vec<char>> = rows
+            .iter()
+            .map(|(_, line)| line[indent..].chars().collect())
+            .collect();
+        let width = symbols.first().map_or(0, |row| row.len());
+        let height = symbols.len();
+        let symbol_at = |x: i32, y: i32| {
+            symbols
+                .get(y as usize)
+                .and_then(|row| row.get(x as usize))
+                .copied()
+        };
+
+        let mut cells = Vec::with_capacity(width * height);
+        let mut sprites = vec![];
+
+        for (y, row) in symbols.iter().enumerate() {
+            let line = rows[y].0;
+            if row.len() != width {
+                return Err(LevelError::at(
+                    line,
+                    indent + 1,
+                    format!("row is {} cells wide, expected {}", row.len(), width),
+                ));
+            }
+
+            for (x, symbol) in row.iter().enumerate() {
+                let mut cell = Cell::new();
+                match *symbol {
+                    SOLID => cell.has_wall = true,
+                    EAST_WALL | NORTH_WALL => {
+                        cell.has_ceiling = true;
+                        add_thin_wall(&mut cell, *symbol == NORTH_WALL, false, false);
+                    }
+                    DOOR | WINDOW => {
+                        let is_wall = |symbol: Option<char>| {
+                            matches!(symbol, Some(SOLID | NORTH_WALL | DOOR | WINDOW))
+                        };
+                        let is_north = is_wall(symbol_at(x as i32 - 1, y as i32))
+                            || is_wall(symbol_at(x as i32 + 1, y as i32));
+                        cell.has_ceiling = true;
+                        add_thin_wall(&mut cell, is_north, *symbol == DOOR, *symbol == WINDOW);
+                    }
+                    FLOOR => cell.has_ceiling = true,
+                    ROAD => cell.has_road = true,
+                    symbol if symbol.is_ascii_alphabetic() => {
+                        cell.has_ceiling = true;
+                        sprites.push(AsciiSprite {
+                            symbol,
+                            x: x as f32 + 0.5,
+                            y: y as f32 + 0.5,
+                            r#type: None,
+                        })
+                    }
+                    symbol => {
+                        return Err(LevelError::at(
+                            line,
+                            indent + x + 1,
+                            format!("unknown map symbol '{}'", symbol),
+                        ))
+                    }
+                }
+                cells.push(cell.to_u64());
+            }
+        }
+
+        for (line, text) in lines.iter().skip(rows.len()) {
+            if text.is_empty() {
+                continue;
+            }
+            let Some((symbol, sprite_type)) = text
+                .split_once('=')
+                .and_then(|(symbol, sprite_type)| {
+                    let mut chars = symbol.trim().chars();
+                    Some((
+                        chars.next().filter(|_| chars.next().is_none())?,
+                        sprite_type,
+                    ))
+                })
+                .and_then(|(symbol, sprite_type)| {
+                    Some((symbol, sprite_type.trim().parse::<i32>().ok()?))
+                })
+            else {
+                return Err(LevelError::at(
+                    *line,
+                    1,
+                    format!(
+                        "expected a sprite type like \"a = 7\", got \"{}\"",
+                        text.trim()
+                    ),
+                ));
+            };
+
+            for sprite in sprites.iter_mut().filter(|sprite| sprite.symbol == symbol) {
+                sprite.r#type = Some(sprite_type);
+            }
+        }
+
+        Ok(AsciiMap {
+            width,
+            height,
+            cells,
+            sprites,
+        })
+    }
+
+    pub fn grid(&self) -> MapGrid<'_> {
+        MapGrid::Dense {
+            data: &self.cells,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+// a full length wall on the east or north edge, one tenth thick
+fn add_thin_wall(cell: &mut Cell, is_north: bool, is_door: bool, is_window: bool) {
+    cell.has_wall = true;
+    cell.num_walls = 1;
+    cell.set_segment(
+        0,
+        WallSegment::new(0, 1, 10, 0, is_north, is_door, is_window),
+    );
+}
+
+#[wasm_bindgen]
+impl Level {
+    /// Builds a level out of an ASCII-art map, see `src/ascii.rs` for the legend. Every sprite
+    /// letter needs a type below the grid.
+    #[wasm_bindgen(js_name = fromAscii)]
+    pub fn from_ascii(text: &str) -> Result<Level, LevelError> {
+        let map = AsciiMap::parse(text)?;
+
+        let mut sprite_data = vec![];
+        for sprite in &map.sprites {
+            let Some(sprite_type) = sprite.r#type else {
+                return Err(LevelError::unpositioned(format!(
+                    "sprite '{}' has no type",
+                    sprite.symbol
+                )));
+            };
+            sprite_data.extend([sprite.x, sprite.y, 0.0, 100.0, sprite_type as f32]);
+        }
+
+        Ok(Level {
+            width: map.width,
+            height: map.height,
+            materials: vec![0; map.cells.len()],
+            grid: map.cells,
+            sprite_data,
+            textures: vec![],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TestRay;
+
+    #[test]
+    fn parses_symbols_and_sprite_types() {
+        let map = AsciiMap::parse(
+            "
+            ###
+            #D#
+            .W|
+            -a=
+
+            a = 7
+            ",
+        )
+        .unwrap();
+
+        assert_eq!((3, 4), (map.width, map.height));
+        assert_eq!(
+            &[
+                0x1,
+                0x1,
+                0x1,
+                0x1,
+                0x0A10_1063,
+                0x1,
+                0x2,
+                0x0A10_1103,
+                0x0A10_1003,
+                0x0A10_1043,
+                0x2,
+                0x8
+            ],
+            map.cells.as_slice()
+        );
+        assert_eq!(
+            vec![AsciiSprite {
+                symbol: 'a',
+                x: 1.5,
+                y: 3.5,
+                r#type: Some(7),
+            }],
+            map.sprites
+        );
+    }
+
+    #[test]
+    fn reports_uneven_rows() {
+        let error = Level::from_ascii("###\n##\n").err().unwrap();
+        assert_eq!((2, 1), (error.line, error.column));
+    }
+
+    #[test]
+    fn casts_rays_against_parsed_maps() {
+        let map = AsciiMap::parse(
+            "
+            #####
+            #...#
+            #####
+            ",
+        )
+        .unwrap();
+        let cast = TestRay::new(map.grid()).cast(1.5, 1.5, 1.0, 0.0);
+
+        assert!((cast.perp_wall_dist - 2.5).abs() < 1e-4);
+    }
+}
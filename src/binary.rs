@@ -0,0 +1,357 @@
+//! Compact binary level container, for shipping levels over the wire.
+//!
+//! All numbers are little endian:
+//!
+//! ```text
+//! magic            4 bytes, "WRCL"
+//! version          u16, the cell layout the file was written with
+//! readable_since   u16, the oldest reader version that understands the file
+//! width, height    u32 each
+//! sections         until the end of the file: tag u16, length u32, then length bytes
+//! ```
+//!
+//! Sections:
+//!
+//! ```text
+//! 1  grid       deflated u64 cells, row by row; the only one that must be there
+//! 2  materials  deflated u64 materials, same size as the grid
+//! 3  sprites    u32 count, then per sprite x, y, angle, height and type as f32
+//! 4  textures   u32 count, then per texture id i32, src (u16 length + UTF-8), width i32,
+//!               height i32, angles u32
+//! ```
+//!
+//! Readers skip sections they don't know, so a writer only bumps `readable_since` when old
+//! readers would get it wrong. Files of older versions are migrated to the current cell
+//! layout as they're read, see `migrate_cell`.
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use wasm_bindgen::prelude::*;
+
+use crate::level::{map_size, Level, LevelError, TextureBinding};
+
+const MAGIC: &[u8; 4] = b"WRCL";
+const VERSION: u16 = 1;
+const READABLE_SINCE: u16 = 1;
+const HEADER_LEN: usize = 16;
+const COMPRESSION_LEVEL: u8 = 9;
+
+const GRID_SECTION: u16 = 1;
+const MATERIALS_SECTION: u16 = 2;
+const SPRITES_SECTION: u16 = 3;
+const TEXTURES_SECTION: u16 = 4;
+
+const SPRITE_FIELDS: usize = 5;
+
+#[wasm_bindgen]
+impl Level {
+    #[wasm_bindgen(js_name = toBinary)]
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&READABLE_SINCE.to_le_bytes());
+        bytes.extend_from_slice(&(self.width as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.height as u32).to_le_bytes());
+
+        write_section(&mut bytes, GRID_SECTION, &deflate(&self.grid));
+        write_section(&mut bytes, MATERIALS_SECTION, &deflate(&self.materials));
+
+        let mut sprites = vec![];
+        sprites.extend_from_slice(&((self.sprite_data.len() / SPRITE_FIELDS) as u32).to_le_bytes());
+        for value in &self.sprite_data {
+            sprites.extend_from_slice(&value.to_le_bytes());
+        }
+        write_section(&mut bytes, SPRITES_SECTION, &sprites);
+
+        let mut textures = vec![];
+        textures.extend_from_slice(&(self.textures.len() as u32).to_le_bytes());
+        for texture in &self.textures {
+            // cut overlong sources between characters, so they still decode
+            let src = &texture.src[..texture.src.floor_char_boundary(u16::MAX as usize)];
+            textures.extend_from_slice(&texture.id.to_le_bytes());
+            textures.extend_from_slice(&(src.len() as u16).to_le_bytes());
+            textures.extend_from_slice(src.as_bytes());
+            textures.extend_from_slice(&texture.width.to_le_bytes());
+            textures.extend_from_slice(&texture.height.to_le_bytes());
+            textures.extend_from_slice(&texture.angles.to_le_bytes());
+        }
+        write_section(&mut bytes, TEXTURES_SECTION, &textures);
+
+        bytes
+    }
+
+    #[wasm_bindgen(js_name = fromBinary)]
+    pub fn from_binary(bytes: &[u8]) -> Result<Level, LevelError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(LevelError::unpositioned(
+                "not a binary level, the magic bytes are missing".to_string(),
+            ));
+        }
+
+        let version = reader.u16()?;
+        let readable_since = reader.u16()?;
+        if readable_since > VERSION {
+            return Err(LevelError::unpositioned(format!(
+                "the level needs version {} to load, this is version {}",
+                readable_since, VERSION
+            )));
+        }
+
+        let width = reader.u32()? as usize;
+        let height = reader.u32()? as usize;
+        // the header isn't trusted, nothing the size of the map is allocated before the grid
+        // section inflates to it
+        let size = map_size(width, height)?;
+
+        let mut grid = None;
+        let mut materials = None;
+        let mut sprite_data = vec![];
+        let mut textures = vec![];
+
+        while !reader.is_at_end() {
+            let tag = reader.u16()?;
+            let len = reader.u32()? as usize;
+            let mut section = Reader {
+                bytes: reader.take(len)?,
+                offset: 0,
+            };
+
+            match tag {
+                GRID_SECTION => {
+                    let mut cells = inflate(section.bytes, size, "grid")?;
+                    for value in cells.iter_mut() {
+                        *value = migrate_cell(version, *value);
+                    }
+                    grid = Some(cells);
+                }
+                MATERIALS_SECTION => materials = Some(inflate(section.bytes, size, "materials")?),
+                SPRITES_SECTION => {
+                    let count = section.u32()? as usize;
+                    let fields = count
+                        .checked_mul(SPRITE_FIELDS)
+                        .filter(|&fields| fields <= section.remaining() / 4)
+                        .ok_or_else(|| {
+                            LevelError::unpositioned(format!(
+                                "the sprites section is too short for {} sprites",
+                                count
+                            ))
+                        })?;
+                    sprite_data = (0..fields)
+                        .map(|_| section.f32())
+                        .collect::<Result<_, _>>()?;
+                }
+                TEXTURES_SECTION => {
+                    let count = section.u32()?;
+                    textures = (0..count)
+                        .map(|_| section.texture())
+                        .collect::<Result<_, _>>()?;
+                }
+                _ => {} // written by a newer version
+            }
+        }
+
+        let grid = grid
+            .ok_or_else(|| LevelError::unpositioned("the grid section is missing".to_string()))?;
+        Ok(Level {
+            width,
+            height,
+            materials: materials.unwrap_or_else(|| vec![0; grid.len()]),
+            grid,
+            sprite_data,
+            textures,
+        })
+    }
+}
+
+/// Converts a cell written with an older layout `version` to the current one. There's only one
+/// layout so far; when the layout changes, bump `VERSION` and convert older cells here.
+fn migrate_cell(_version: u16, value: u64) -> u64 {
+    value
+}
+
+fn write_section(bytes: &mut Vec<u8>, tag: u16, section: &[u8]) {
+    bytes.extend_from_slice(&tag.to_le_bytes());
+    bytes.extend_from_slice(&(section.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(section);
+}
+
+fn deflate(values: &[u64]) -> Vec<u8> {
+    let bytes: Vec<u8> = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    compress_to_vec(&bytes, COMPRESSION_LEVEL)
+}
+
+fn inflate(bytes: &[u8], size: usize, name: &str) -> Result<Vec<u64>, LevelError> {
+    // limited, so a corrupt section can't inflate into more than the map holds
+    let bytes = decompress_to_vec_with_limit(bytes, size.saturating_mul(8))
+        .map_err(|_| LevelError::unpositioned(format!("the {} section is corrupt", name)))?;
+    if bytes.len() != size * 8 {
+        return Err(LevelError::unpositioned(format!(
+            "the {} section holds {} cells, expected {}",
+            name,
+            bytes.len() / 8,
+            size
+        )));
+    }
+
+    Ok(bytes
+        .as_chunks::<8>()
+        .0
+        .iter()
+        .map(|value| u64::from_le_bytes(*value))
+        .collect())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn is_at_end(&self) -> bool {
+        self.offset >= self.bytes.len()
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.offset)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], LevelError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or_else(|| {
+                LevelError::unpositioned(format!(
+                    "the level ends early, expected {} more bytes at byte {}",
+                    len, self.offset
+                ))
+            })?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LevelError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u16(&mut self) -> Result<u16, LevelError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, LevelError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn i32(&mut self) -> Result<i32, LevelError> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    fn f32(&mut self) -> Result<f32, LevelError> {
+        Ok(f32::from_le_bytes(self.array()?))
+    }
+
+    fn texture(&mut self) -> Result<TextureBinding, LevelError> {
+        let id = self.i32()?;
+        let len = self.u16()? as usize;
+        let src = String::from_utf8(self.take(len)?.to_vec()).map_err(|_| {
+            LevelError::unpositioned(format!("the src of texture {} isn't UTF-8", id))
+        })?;
+
+        Ok(TextureBinding {
+            id,
+            src,
+            width: self.i32()?,
+            height: self.i32()?,
+            angles: self.u32()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level() -> Level {
+        Level::from_json(
+            r#"{
+                "width": 40, "height": 30,
+                "default": { "road": true },
+                "cells": [{ "x": 3, "y": 2, "ceiling": true, "floor_texture": 100,
+                            "walls": [{ "thickness": 1, "depth": 10, "north": true, "texture": 100 }] }],
+                "sprites": [{ "x": 1.5, "y": 0.5, "angle": 90, "height": 0.5, "type": 7 }],
+                "textures": [{ "id": 100, "src": "brick.png", "width": 512, "height": 512 }]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_levels() {
+        let level = level();
+        let bytes = level.to_binary();
+        // a u64 per cell would be 9600 bytes for the grid alone
+        assert!(bytes.len() < 200);
+
+        let decoded = Level::from_binary(&bytes).unwrap();
+        assert_eq!((40, 30), (decoded.width, decoded.height));
+        assert_eq!(level.get_grid(), decoded.get_grid());
+        assert_eq!(level.get_materials(), decoded.get_materials());
+        assert_eq!(level.sprite_data(), decoded.sprite_data());
+        assert_eq!("brick.png", decoded.get_textures()[0].src);
+        assert_eq!(1, decoded.get_textures()[0].angles);
+    }
+
+    #[test]
+    fn cuts_overlong_sources_between_characters() {
+        // 2 bytes per character, so u16::MAX falls inside one
+        let src = "é".repeat(40_000);
+        let level = Level::from_json(&format!(
+            r#"{{ "width": 1, "height": 1,
+                 "textures": [{{ "id": 100, "src": "{}", "width": 64, "height": 64 }}] }}"#,
+            src
+        ))
+        .unwrap();
+
+        let decoded = Level::from_binary(&level.to_binary()).unwrap();
+        let cut = &decoded.get_textures()[0].src;
+        assert_eq!(u16::MAX as usize - 1, cut.len());
+        assert!(src.starts_with(cut.as_str()));
+    }
+
+    #[test]
+    fn skips_unknown_sections_and_rejects_newer_layouts() {
+        let mut bytes = level().to_binary();
+        write_section(&mut bytes, 99, &[1, 2, 3]);
+        assert!(Level::from_binary(&bytes).is_ok());
+
+        bytes[6..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(Level::from_binary(&bytes).is_err());
+
+        assert!(Level::from_binary(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_the_sections_dont_hold() {
+        let bytes = level().to_binary();
+        // a header asking for a huge map, without anything to fill it with
+        let mut huge = bytes[..HEADER_LEN].to_vec();
+        huge[8..16].copy_from_slice(&[0xff; 8]);
+        assert!(Level::from_binary(&huge).is_err());
+        huge[8..16].copy_from_slice(&[0, 0x10, 0, 0, 0, 0x10, 0, 0]);
+        assert!(Level::from_binary(&huge).is_err());
+
+        // a grid that doesn't match the header
+        let mut wider = bytes.clone();
+        wider[8..12].copy_from_slice(&41u32.to_le_bytes());
+        assert!(Level::from_binary(&wider).is_err());
+
+        let mut sprites = bytes[..HEADER_LEN].to_vec();
+        write_section(&mut sprites, GRID_SECTION, &deflate(&[0; 40 * 30]));
+        write_section(&mut sprites, SPRITES_SECTION, &u32::MAX.to_le_bytes());
+        assert!(Level::from_binary(&sprites).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Where the view is rendered from: position, which way it looks and how wide it sees.
+//!
+//! The raycaster works with a unit direction and a camera plane (see `Position`), whose length
+//! sets the field of view. `Camera` derives both from a yaw and a field of view in degrees, for
+//! the size of the screen at hand, so the field of view can change from frame to frame to zoom,
+//! and wider screens see more to the sides rather than less above and below.
+
+use wasm_bindgen::prelude::*;
+
+use crate::helpers::Position;
+
+/// Eyes half way up a wall, which puts the middle of every wall on the horizon.
+pub const STANDING_EYE_HEIGHT: f32 = 0.5;
+
+// the field of view is the horizontal one on a screen of this shape
+const REFERENCE_ASPECT_RATIO: f32 = 4.0 / 3.0;
+const MIN_FOV: f32 = 10.0;
+const MAX_FOV: f32 = 160.0;
+
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub x: f32,
+    pub y: f32,
+    /// In radians, 0 looking east, growing when turning left, towards the south.
+    pub yaw: f32,
+    fov: f32,
+    /// In pixels the horizon moves down, looking up.
+    pub pitch: i32,
+    /// Eye height above the floor of the lowest layer, in wall heights; 0.5 standing.
+    pub z: f32,
+}
+
+#[wasm_bindgen]
+impl Camera {
+    #[wasm_bindgen(constructor)]
+    pub fn new(x: f32, y: f32, yaw: f32, fov: f32, pitch: i32, z: f32) -> Camera {
+        Camera {
+            x,
+            y,
+            yaw,
+            fov: fov.clamp(MIN_FOV, MAX_FOV),
+            pitch,
+            z,
+        }
+    }
+
+    /// Horizontal field of view in degrees, as seen on a 4:3 screen.
+    #[wasm_bindgen(getter)]
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+
+    /// Zooms in or out, between 10 and 160 degrees.
+    #[wasm_bindgen(setter)]
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov.clamp(MIN_FOV, MAX_FOV);
+    }
+
+    #[wasm_bindgen(getter = dirX)]
+    pub fn dir_x(&self) -> f32 {
+        self.dir().0
+    }
+
+    #[wasm_bindgen(getter = dirY)]
+    pub fn dir_y(&self) -> f32 {
+        self.dir().1
+    }
+
+    /// Elevation of the feet, in wall heights, e.g. for `WasmMapLayers.layerAt`.
+    #[wasm_bindgen(getter)]
+    pub fn feet(&self) -> f32 {
+        self.z - STANDING_EYE_HEIGHT
+    }
+
+    /// Turns left by `radians`, right if negative.
+    pub fn turn(&mut self, radians: f32) {
+        self.yaw = (self.yaw + radians).rem_euclid(std::f32::consts::TAU);
+    }
+}
+
+// 🦀 Rust-only implementation block
+impl Camera {
+    /// Unit vector along the view.
+    pub fn dir(&self) -> (f32, f32) {
+        let (sin, cos) = self.yaw.sin_cos();
+        (cos, sin)
+    }
+
+    /// From the middle of the screen to its right edge, for a screen of `width` by `height`.
+    pub fn plane(&self, width: i32, height: i32) -> (f32, f32) {
+        let (dir_x, dir_y) = self.dir();
+        let aspect_ratio = width as f32 / height.max(1) as f32;
+        let length = (self.fov.to_radians() / 2.0).tan() * aspect_ratio / REFERENCE_ASPECT_RATIO;
+
+        (dir_y * length, -dir_x * length)
+    }
+
+    /// The same camera at (x, y), looking along `dir`, e.g. after walking through a portal.
+    pub fn looking(&self, x: f32, y: f32, (dir_x, dir_y): (f32, f32)) -> Camera {
+        Camera {
+            x,
+            y,
+            yaw: dir_y.atan2(dir_x),
+            ..*self
+        }
+    }
+
+    pub fn position(&self, width: i32, height: i32) -> Position {
+        let (dir_x, dir_y) = self.dir();
+        let (plane_x, plane_y) = self.plane(width, height);
+
+        Position {
+            x: self.x,
+            y: self.y,
+            map_x: self.x.floor() as i32,
+            map_y: self.y.floor() as i32,
+            dir_x,
+            dir_y,
+            plane_x,
+            plane_y,
+            pitch: self.pitch,
+            z: self.z,
+            plane_y_initial: plane_x.hypot(plane_y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::translate_coordinate_to_camera;
+
+    #[test]
+    fn derives_view_vectors() {
+        let mut camera = Camera::new(1.5, 2.5, 0.0, 90.0, 0, STANDING_EYE_HEIGHT);
+        let position = camera.position(400, 300);
+        assert_eq!((1.0, 0.0), (position.dir_x, position.dir_y));
+        assert!(position.plane_x.abs() < 1e-6 && (position.plane_y + 1.0).abs() < 1e-6);
+        assert_eq!((1, 2), (position.map_x, position.map_y));
+
+        // wider screens see more to the sides
+        let wide = camera.position(800, 300);
+        assert!((wide.plane_y_initial - 2.0).abs() < 1e-5);
+
+        camera.turn(std::f32::consts::FRAC_PI_2);
+        let (dir_x, dir_y) = camera.dir();
+        assert!(dir_x.abs() < 1e-6 && (dir_y - 1.0).abs() < 1e-6);
+        camera.set_fov(500.0);
+        assert_eq!(MAX_FOV, camera.fov());
+    }
+
+    #[test]
+    fn keeps_walls_square_at_any_aspect_ratio() {
+        let camera = Camera::new(0.0, 0.0, 0.0, 60.0, 0, STANDING_EYE_HEIGHT);
+        // a wall two cells ahead is as high on every screen of the same height, zoom aside
+        let wall_height = |width: i32, camera: &Camera| {
+            let position = camera.position(width, 300);
+            let inv_det =
+                (position.plane_x * position.dir_y - position.dir_x * position.plane_y).abs();
+            translate_coordinate_to_camera(&position, 2.0, 0.0, 1.0, width, 300, inv_det)
+                .full_height
+        };
+
+        assert_eq!(wall_height(400, &camera), wall_height(1200, &camera));
+        let zoomed = Camera::new(0.0, 0.0, 0.0, 30.0, 0, STANDING_EYE_HEIGHT);
+        assert!(wall_height(400, &zoomed) > 2 * wall_height(400, &camera));
+    }
+
+    #[test]
+    fn projects_eye_height_alike_at_any_resolution() {
+        let flying = Camera::new(0.0, 0.0, 0.0, 60.0, 0, 0.9);
+        // where the bottom of a wall two cells ahead is, as a part of the screen height
+        let wall_bottom = |width: i32, height: i32| {
+            let position = flying.position(width, height);
+            let inv_det = position.plane_y_initial; // the direction is a unit vector
+            let projection =
+                translate_coordinate_to_camera(&position, 2.0, 0.0, 1.0, width, height, inv_det);
+            (projection.screen_y_ceiling + projection.full_height) as f32 / height as f32
+        };
+
+        assert!((wall_bottom(400, 300) - wall_bottom(1600, 1200)).abs() < 0.01);
+        // 0.9 of the wall's height below the horizon, which is 173 pixels high at 400 by 300
+        assert!((wall_bottom(400, 300) - (150.0 + 0.9 * 173.0) / 300.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,561 @@
+//! Typed view over the u64 bitfield stored per grid coordinate.
+//!
+//! Layout, bit 0 being the least significant:
+//!
+//! ```text
+//! 0      wall
+//! 1      ceiling & floor
+//! 2      north (segment 2)
+//! 3      road
+//! 4      door (segments 1 and 2 share this bit)
+//! 5      door (segment 0)
+//! 6      north (segment 0)
+//! 7      north (segment 1)
+//! 8      window (segment 0)
+//! 9      window (segment 1)
+//! 10-11  geometry of the segments, see `CellGeometry`
+//! 12-15  number of wall segments
+//! 16-31  segment 0: offset, thickness, depth, offset secondary (4 bits each)
+//! 32-47  segment 1
+//! 48-63  segment 2
+//! ```
+//!
+//! Offsets are in tenths of a cell; the primary offset is measured from the
+//! east (or north, for north facing segments), the secondary one along the wall.
+//! In cells with `CellGeometry::Lines` the same four nibbles are the two endpoints
+//! of a thin wall at any angle instead, with `CellGeometry::Cylinder` the centre
+//! and radius of a round pillar.
+//!
+//! Since the cell bitfield is full, everything else about a cell lives in a
+//! parallel material layer of the same size, see `CellMaterial`.
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+use crate::helpers::{get_bits, has_bit_set};
+
+pub const MAX_WALL_SEGMENTS: usize = 3;
+
+const WALL_BIT: u8 = 0;
+const CEILING_BIT: u8 = 1;
+const ROAD_BIT: u8 = 3;
+const GEOMETRY_OFFSET: u8 = 10;
+const GEOMETRY_MASK: u64 = 0b11 << GEOMETRY_OFFSET;
+const NUM_WALLS_OFFSET: u8 = 12;
+const SEGMENTS_OFFSET: u8 = 16;
+const SEGMENT_BITS: u8 = 16;
+
+// per segment bits of (north, door, window); segment 2 has no window bit
+const NORTH_BITS: [u8; MAX_WALL_SEGMENTS] = [6, 7, 2];
+const DOOR_BITS: [u8; MAX_WALL_SEGMENTS] = [5, 4, 4];
+const WINDOW_BITS: [Option<u8>; MAX_WALL_SEGMENTS] = [Some(8), Some(9), None];
+
+/// One thin wall inside a cell. Nibble values are kept raw (0-15) so that a
+/// decoded cell always encodes back to the exact same u64.
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WallSegment {
+    pub offset: u8,
+    pub thickness: u8,
+    pub depth: u8,
+    pub offset_secondary: u8,
+    pub is_north: bool,
+    pub is_door: bool,
+    pub is_window: bool,
+}
+
+#[wasm_bindgen]
+impl WallSegment {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        offset: u8,
+        thickness: u8,
+        depth: u8,
+        offset_secondary: u8,
+        is_north: bool,
+        is_door: bool,
+        is_window: bool,
+    ) -> WallSegment {
+        WallSegment {
+            offset,
+            thickness,
+            depth,
+            offset_secondary,
+            is_north,
+            is_door,
+            is_window,
+        }
+    }
+
+    /// A thin wall from (start_x, start_y) to (end_x, end_y), in tenths of a cell,
+    /// for cells with `CellGeometry::Lines`.
+    #[wasm_bindgen]
+    pub fn line(
+        start_x: u8,
+        start_y: u8,
+        end_x: u8,
+        end_y: u8,
+        is_door: bool,
+        is_window: bool,
+    ) -> WallSegment {
+        WallSegment {
+            offset: start_x,
+            thickness: start_y,
+            depth: end_x,
+            offset_secondary: end_y,
+            is_north: false,
+            is_door,
+            is_window,
+        }
+    }
+
+    /// A round pillar around (centre_x, centre_y), in tenths of a cell,
+    /// for cells with `CellGeometry::Cylinder`.
+    #[wasm_bindgen]
+    pub fn cylinder(centre_x: u8, centre_y: u8, radius: u8) -> WallSegment {
+        WallSegment {
+            offset: centre_x,
+            thickness: centre_y,
+            depth: radius,
+            ..WallSegment::default()
+        }
+    }
+}
+
+impl WallSegment {
+    /// Endpoints in cell units, for segments of `CellGeometry::Lines` cells.
+    #[inline(always)]
+    pub fn endpoints(&self) -> ((f32, f32), (f32, f32)) {
+        let unit = |nibble: u8| (nibble % 11) as f32 / 10.0;
+        (
+            (unit(self.offset), unit(self.thickness)),
+            (unit(self.depth), unit(self.offset_secondary)),
+        )
+    }
+
+    /// Centre and radius in cell units, for segments of `CellGeometry::Cylinder` cells.
+    #[inline(always)]
+    pub fn circle(&self) -> ((f32, f32), f32) {
+        let unit = |nibble: u8| (nibble % 11) as f32 / 10.0;
+        ((unit(self.offset), unit(self.thickness)), unit(self.depth))
+    }
+
+    #[inline(always)]
+    fn from_u64(value: u64, index: usize) -> WallSegment {
+        let start = SEGMENTS_OFFSET + SEGMENT_BITS * index as u8;
+
+        WallSegment {
+            offset: get_bits(value, start),
+            thickness: get_bits(value, start + 4),
+            depth: get_bits(value, start + 8),
+            offset_secondary: get_bits(value, start + 12),
+            is_north: has_bit_set(value, NORTH_BITS[index]),
+            is_door: has_bit_set(value, DOOR_BITS[index]),
+            is_window: WINDOW_BITS[index].is_some_and(|bit| has_bit_set(value, bit)),
+        }
+    }
+
+    fn to_u64(self, index: usize) -> u64 {
+        let start = SEGMENTS_OFFSET + SEGMENT_BITS * index as u8;
+
+        let mut value = (nibble(self.offset) << start)
+            | (nibble(self.thickness) << (start + 4))
+            | (nibble(self.depth) << (start + 8))
+            | (nibble(self.offset_secondary) << (start + 12));
+
+        value |= flag(self.is_north, NORTH_BITS[index]);
+        value |= flag(self.is_door, DOOR_BITS[index]);
+        if let Some(bit) = WINDOW_BITS[index] {
+            value |= flag(self.is_window, bit);
+        }
+
+        value
+    }
+}
+
+/// How the segments of a cell are laid out.
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CellGeometry {
+    /// Axis aligned walls given by offsets, thickness and depth.
+    #[default]
+    Aligned = 0,
+    /// Thin walls between two points, see `WallSegment::line`.
+    Lines = 1,
+    /// Round pillars, see `WallSegment::cylinder`.
+    Cylinder = 2,
+}
+
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Cell {
+    pub has_wall: bool,
+    pub has_ceiling: bool,
+    pub has_road: bool,
+    pub num_walls: u8,
+    pub geometry: CellGeometry,
+    segments: [WallSegment; MAX_WALL_SEGMENTS],
+    reserved: u64, // geometry bits of kinds we don't know, kept as they are
+}
+
+#[wasm_bindgen]
+impl Cell {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Cell {
+        Cell::default()
+    }
+
+    #[wasm_bindgen(js_name = fromU64)]
+    #[inline(always)]
+    pub fn from_u64(value: u64) -> Cell {
+        let geometry = match (value & GEOMETRY_MASK) >> GEOMETRY_OFFSET {
+            0 => Some(CellGeometry::Aligned),
+            1 => Some(CellGeometry::Lines),
+            2 => Some(CellGeometry::Cylinder),
+            _ => None,
+        };
+
+        Cell {
+            has_wall: has_bit_set(value, WALL_BIT),
+            has_ceiling: has_bit_set(value, CEILING_BIT),
+            has_road: has_bit_set(value, ROAD_BIT),
+            num_walls: get_bits(value, NUM_WALLS_OFFSET),
+            geometry: geometry.unwrap_or_default(),
+            segments: [
+                WallSegment::from_u64(value, 0),
+                WallSegment::from_u64(value, 1),
+                WallSegment::from_u64(value, 2),
+            ],
+            reserved: if geometry.is_some() {
+                0
+            } else {
+                value & GEOMETRY_MASK
+            },
+        }
+    }
+
+    /// Segments 1 and 2 share a door bit, so a door on either of them marks both.
+    #[wasm_bindgen(js_name = toU64)]
+    pub fn to_u64(&self) -> u64 {
+        let mut value = flag(self.has_wall, WALL_BIT)
+            | flag(self.has_ceiling, CEILING_BIT)
+            | flag(self.has_road, ROAD_BIT)
+            | (nibble(self.num_walls) << NUM_WALLS_OFFSET)
+            | ((self.geometry as u64) << GEOMETRY_OFFSET)
+            | self.reserved;
+
+        for (index, segment) in self.segments.iter().enumerate() {
+            value |= segment.to_u64(index);
+        }
+
+        value
+    }
+
+    /// Returns segment `index` regardless of `num_walls`.
+    pub fn segment(&self, index: usize) -> Option<WallSegment> {
+        self.segments.get(index).copied()
+    }
+
+    #[wasm_bindgen(js_name = setSegment)]
+    pub fn set_segment(&mut self, index: usize, segment: WallSegment) {
+        if let Some(slot) = self.segments.get_mut(index) {
+            *slot = segment;
+        }
+    }
+
+    /// A wall without any thin segments fills the whole cell, whatever the other bits are, so a
+    /// solid wall with a ceiling (`0x3`) is as solid as a bare one (`0x1`).
+    #[wasm_bindgen(js_name = isSolid)]
+    pub fn is_solid(&self) -> bool {
+        self.has_wall && self.num_walls == 0
+    }
+}
+
+impl Cell {
+    /// The segments in use, capped at the number the layout can hold.
+    #[inline(always)]
+    pub fn walls(&self) -> &[WallSegment] {
+        &self.segments[..(self.num_walls as usize).min(MAX_WALL_SEGMENTS)]
+    }
+
+    /// The geometry bits hold a kind this version doesn't know, which is drawn as `Aligned`.
+    pub fn has_unknown_geometry(&self) -> bool {
+        self.reserved != 0
+    }
+}
+
+/// Per-cell data of the parallel material layer. A texture of 0 means the default
+/// texture for what was hit (`WALL`, `DOOR` or `WINDOW`); for the floor it is `ROAD`
+/// or `FLOOR` depending on the cell bits, for the ceiling `CEILING`. A cell with its
+/// own floor or ceiling texture gets that plane drawn even without the ceiling bit.
+///
+/// ```text
+/// 0-7    texture of segment 0
+/// 8-15   texture of segment 1
+/// 16-23  texture of segment 2
+/// 24-31  floor texture
+/// 32-39  ceiling texture
+/// 40-51  wall height of segments 0, 1 and 2, 4 bits each (segment 0 for solid cells)
+/// 52-54  mirror, segments 0, 1 and 2 (segment 0 for solid cells)
+/// 55-63  reserved
+/// ```
+///
+/// Wall heights are in quarters of the default wall height, 0 meaning the default
+/// of a full wall, so they range from 0.25 (a low fence) to 3.75 (a tower).
+#[wasm_bindgen]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CellMaterial {
+    pub floor_texture: u8,
+    pub ceiling_texture: u8,
+    wall_textures: [u8; MAX_WALL_SEGMENTS],
+    wall_heights: [u8; MAX_WALL_SEGMENTS],
+    mirrors: [bool; MAX_WALL_SEGMENTS],
+    reserved: u64,
+}
+
+const WALL_TEXTURES_OFFSET: u8 = 0;
+const FLOOR_TEXTURE_OFFSET: u8 = 24;
+const CEILING_TEXTURE_OFFSET: u8 = 32;
+const WALL_HEIGHTS_OFFSET: u8 = 40;
+const MIRRORS_OFFSET: u8 = 52;
+const MATERIAL_RESERVED_MASK: u64 = !0 << 55;
+const WALL_HEIGHT_STEPS: f32 = 4.0;
+
+#[wasm_bindgen]
+impl CellMaterial {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CellMaterial {
+        CellMaterial::default()
+    }
+
+    #[wasm_bindgen(js_name = fromU64)]
+    #[inline(always)]
+    pub fn from_u64(value: u64) -> CellMaterial {
+        CellMaterial {
+            floor_texture: byte(value, FLOOR_TEXTURE_OFFSET),
+            ceiling_texture: byte(value, CEILING_TEXTURE_OFFSET),
+            wall_textures: [0, 1, 2].map(|index| byte(value, WALL_TEXTURES_OFFSET + 8 * index)),
+            wall_heights: [0, 1, 2].map(|index| get_bits(value, WALL_HEIGHTS_OFFSET + 4 * index)),
+            mirrors: [0, 1, 2].map(|index| has_bit_set(value, MIRRORS_OFFSET + index)),
+            reserved: value & MATERIAL_RESERVED_MASK,
+        }
+    }
+
+    #[wasm_bindgen(js_name = toU64)]
+    pub fn to_u64(&self) -> u64 {
+        let mut value = self.reserved
+            | ((self.floor_texture as u64) << FLOOR_TEXTURE_OFFSET)
+            | ((self.ceiling_texture as u64) << CEILING_TEXTURE_OFFSET);
+
+        for (index, texture) in self.wall_textures.iter().enumerate() {
+            value |= (*texture as u64) << (WALL_TEXTURES_OFFSET as usize + 8 * index);
+        }
+        for (index, steps) in self.wall_heights.iter().enumerate() {
+            value |= nibble(*steps) << (WALL_HEIGHTS_OFFSET as usize + 4 * index);
+        }
+        for (index, is_mirror) in self.mirrors.iter().enumerate() {
+            value |= flag(*is_mirror, MIRRORS_OFFSET + index as u8);
+        }
+
+        value
+    }
+
+    #[wasm_bindgen(js_name = wallTexture)]
+    pub fn wall_texture(&self, index: usize) -> u8 {
+        self.wall_textures.get(index).copied().unwrap_or(0)
+    }
+
+    #[wasm_bindgen(js_name = setWallTexture)]
+    pub fn set_wall_texture(&mut self, index: usize, texture: u8) {
+        if let Some(slot) = self.wall_textures.get_mut(index) {
+            *slot = texture;
+        }
+    }
+
+    /// Whether segment `index` reflects, like a mirror. Doors and windows don't.
+    #[wasm_bindgen(js_name = isMirror)]
+    #[inline(always)]
+    pub fn is_mirror(&self, index: usize) -> bool {
+        self.mirrors.get(index).copied().unwrap_or(false)
+    }
+
+    #[wasm_bindgen(js_name = setMirror)]
+    pub fn set_mirror(&mut self, index: usize, is_mirror: bool) {
+        if let Some(slot) = self.mirrors.get_mut(index) {
+            *slot = is_mirror;
+        }
+    }
+
+    /// Height of segment `index` in wall heights, 1.0 being a regular wall.
+    #[wasm_bindgen(js_name = wallHeight)]
+    #[inline(always)]
+    pub fn wall_height(&self, index: usize) -> f32 {
+        match self.wall_heights.get(index).copied().unwrap_or(0) {
+            0 => 1.0,
+            steps => steps as f32 / WALL_HEIGHT_STEPS,
+        }
+    }
+
+    /// The height of the tallest segment, at least 1.0 as unused segments read as full walls.
+    pub fn tallest_wall(&self) -> f32 {
+        (0..MAX_WALL_SEGMENTS)
+            .map(|index| self.wall_height(index))
+            .fold(1.0, f32::max)
+    }
+
+    /// Rounds to the nearest quarter, clamped to 0.25 - 3.75.
+    #[wasm_bindgen(js_name = setWallHeight)]
+    pub fn set_wall_height(&mut self, index: usize, height: f32) {
+        let steps = (height * WALL_HEIGHT_STEPS).round().clamp(1.0, 15.0) as u8;
+        if let Some(slot) = self.wall_heights.get_mut(index) {
+            *slot = if steps == WALL_HEIGHT_STEPS as u8 {
+                0
+            } else {
+                steps
+            };
+        }
+    }
+}
+
+#[inline(always)]
+fn byte(value: u64, start_bit: u8) -> u8 {
+    ((value >> start_bit) & 0xFF) as u8
+}
+
+#[inline(always)]
+fn nibble(value: u8) -> u64 {
+    (value & 0b1111) as u64
+}
+
+#[inline(always)]
+fn flag(is_set: bool, bit: u8) -> u64 {
+    (is_set as u64) << bit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a few cells from the demo grid map
+    const GRID_VALUES: [u64; 8] = [
+        0x0000000000000008,
+        0x000000000A191059,
+        0x00000A100A102087,
+        0x00000110191020A9,
+        0x00000510550021C7,
+        0x000019000A0A2347,
+        0x0A190A190A103083,
+        0x0000000000000006,
+    ];
+
+    #[test]
+    fn round_trips_grid_values() {
+        for value in GRID_VALUES {
+            assert_eq!(value, Cell::from_u64(value).to_u64());
+        }
+    }
+
+    #[test]
+    fn round_trips_reserved_bits() {
+        let value = 0xFFFF_FFFF_FFFF_FFFF;
+        assert_eq!(value, Cell::from_u64(value).to_u64());
+    }
+
+    #[test]
+    fn decodes_segments() {
+        let cell = Cell::from_u64(0x00000A100A102087);
+
+        assert!(cell.has_wall);
+        assert!(cell.has_ceiling);
+        assert!(!cell.has_road);
+        assert_eq!(2, cell.walls().len());
+        assert_eq!(
+            WallSegment::new(0, 1, 10, 0, false, false, false),
+            cell.walls()[0]
+        );
+        assert_eq!(
+            WallSegment::new(0, 1, 10, 0, true, false, false),
+            cell.walls()[1]
+        );
+    }
+
+    #[test]
+    fn tells_solid_cells_from_thin_walls() {
+        // the bare wall bit, and with the ceiling and road bits set as well
+        for value in [0x1, 0x3, 0xB] {
+            assert!(Cell::from_u64(value).is_solid(), "{:#x}", value);
+        }
+        for value in [0x0, 0x2, 0x8, 0x0A10_1041] {
+            assert!(!Cell::from_u64(value).is_solid(), "{:#x}", value);
+        }
+    }
+
+    #[test]
+    fn encodes_built_cell() {
+        let mut cell = Cell::new();
+        cell.has_wall = true;
+        cell.has_road = true;
+        cell.num_walls = 1;
+        cell.set_segment(0, WallSegment::new(0, 1, 10, 0, true, false, false));
+
+        assert_eq!(0x000000000A101049, cell.to_u64());
+    }
+
+    #[test]
+    fn encodes_line_segments() {
+        let mut cell = Cell::new();
+        cell.has_wall = true;
+        cell.num_walls = 1;
+        cell.geometry = CellGeometry::Lines;
+        cell.set_segment(0, WallSegment::line(0, 2, 10, 8, false, true));
+
+        let value = cell.to_u64();
+        assert_eq!(0x00000000_8A201501, value);
+
+        let decoded = Cell::from_u64(value);
+        assert_eq!(CellGeometry::Lines, decoded.geometry);
+        assert_eq!(((0.0, 0.2), (1.0, 0.8)), decoded.walls()[0].endpoints());
+    }
+
+    #[test]
+    fn decodes_cylinders() {
+        let mut cell = Cell::new();
+        cell.has_wall = true;
+        cell.num_walls = 1;
+        cell.geometry = CellGeometry::Cylinder;
+        cell.set_segment(0, WallSegment::cylinder(5, 5, 3));
+
+        let decoded = Cell::from_u64(cell.to_u64());
+        assert_eq!(CellGeometry::Cylinder, decoded.geometry);
+        assert_eq!(((0.5, 0.5), 0.3), decoded.walls()[0].circle());
+    }
+
+    #[test]
+    fn round_trips_material() {
+        let value = 0xDEAD_020E_0364_0C01;
+        let material = CellMaterial::from_u64(value);
+
+        assert_eq!(0.5, material.wall_height(0));
+        assert_eq!(1.0, material.wall_height(1));
+        assert_eq!(3.25, material.wall_height(2));
+        assert_eq!(3, material.floor_texture);
+        assert_eq!(14, material.ceiling_texture);
+        assert_eq!(1, material.wall_texture(0));
+        assert_eq!(12, material.wall_texture(1));
+        assert_eq!(100, material.wall_texture(2));
+        assert!(material.is_mirror(1) && !material.is_mirror(0));
+        assert_eq!(value, material.to_u64());
+    }
+
+    #[test]
+    fn stores_full_height_as_default() {
+        let mut material = CellMaterial::new();
+        material.set_wall_height(0, 1.0);
+        material.set_wall_height(1, 0.3);
+        material.set_wall_height(2, 8.0);
+
+        assert_eq!(0.25, material.wall_height(1));
+        assert_eq!(3.75, material.wall_height(2));
+        assert_eq!(0x000F_1000_0000_0000, material.to_u64());
+    }
+}
@@ -0,0 +1,201 @@
+//! Open state of the doors of a layer, keyed by cell.
+//!
+//! The door bits of a cell only say where its doors are; how far they're open and how they open
+//! lives here, so a door that was never touched is simply closed. Opening and closing sets where a
+//! door is headed and `update` moves it there over time.
+
+use std::collections::BTreeMap;
+use std::f32::consts::FRAC_PI_2;
+
+use wasm_bindgen::prelude::*;
+
+/// How a door moves out of the way, seen from the west (east doors) or the north (north doors).
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DoorStyle {
+    /// Slides along the wall, towards the north or the west end.
+    #[default]
+    SlideLeft = 0,
+    /// Slides along the wall, towards the south or the east end.
+    SlideRight = 1,
+    /// Slides up into the wall above.
+    SlideUp = 2,
+    /// Swings a quarter turn around a hinge at the north or west end, to the east or south.
+    Swing = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoorState {
+    pub style: DoorStyle,
+    pub open: f32,   // 0 closed, 1 fully open
+    pub target: f32, // where the door is headed
+    pub speed: f32,  // in open fractions per second
+}
+
+impl DoorState {
+    /// One second to open or close.
+    pub const DEFAULT_SPEED: f32 = 1.0;
+
+    pub fn is_open(&self) -> bool {
+        self.open >= 1.0
+    }
+
+    /// How far the door is raised, in door heights.
+    pub fn lift(&self) -> f32 {
+        if self.style == DoorStyle::SlideUp {
+            self.open
+        } else {
+            0.0
+        }
+    }
+
+    /// The part of the door that's still in the doorway, which runs from `from` to `from + depth`
+    /// along the wall, with the door `across` into the cell. None once it has slid out of the way.
+    pub fn panel(&self, from: f32, depth: f32, across: f32) -> Option<DoorPanel> {
+        let shift = self.open * depth;
+        let (start, end, u_start, u_end) = match self.style {
+            DoorStyle::SlideLeft => (
+                (from, across),
+                (from + depth - shift, across),
+                self.open,
+                1.0,
+            ),
+            DoorStyle::SlideRight => (
+                (from + shift, across),
+                (from + depth, across),
+                0.0,
+                1.0 - self.open,
+            ),
+            DoorStyle::SlideUp => ((from, across), (from + depth, across), 0.0, 1.0),
+            DoorStyle::Swing => {
+                let (sin, cos) = (self.open * FRAC_PI_2).sin_cos();
+                (
+                    (from, across),
+                    (from + depth * cos, across + depth * sin),
+                    0.0,
+                    1.0,
+                )
+            }
+        };
+
+        (u_end - u_start > 0.0 && depth > 0.0).then_some(DoorPanel {
+            start,
+            end,
+            u_start,
+            u_end,
+        })
+    }
+}
+
+/// A door as a line, in (along, across) coordinates of its wall, with the horizontal texture
+/// coordinates at both of its ends.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DoorPanel {
+    pub start: (f32, f32),
+    pub end: (f32, f32),
+    pub u_start: f32,
+    pub u_end: f32,
+}
+
+impl Default for DoorState {
+    fn default() -> Self {
+        DoorState {
+            style: DoorStyle::default(),
+            open: 0.0,
+            target: 0.0,
+            speed: DoorState::DEFAULT_SPEED,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct DoorStates {
+    doors: BTreeMap<(i32, i32), DoorState>,
+}
+
+impl DoorStates {
+    pub const EMPTY: DoorStates = DoorStates {
+        doors: BTreeMap::new(),
+    };
+
+    /// Only doors that were set up or opened have a state, the others are closed.
+    #[inline(always)]
+    pub fn get(&self, x: i32, y: i32) -> Option<&DoorState> {
+        if self.doors.is_empty() {
+            return None;
+        }
+        self.doors.get(&(x, y))
+    }
+
+    pub fn is_open(&self, x: i32, y: i32) -> bool {
+        self.get(x, y).is_some_and(DoorState::is_open)
+    }
+
+    pub fn open_fraction(&self, x: i32, y: i32) -> f32 {
+        self.get(x, y).map_or(0.0, |door| door.open)
+    }
+
+    /// Keeps how far the door is open.
+    pub fn set_style(&mut self, x: i32, y: i32, style: DoorStyle, speed: f32) {
+        let door = self.doors.entry((x, y)).or_default();
+        door.style = style;
+        door.speed = speed;
+    }
+
+    pub fn open(&mut self, x: i32, y: i32) {
+        self.doors.entry((x, y)).or_default().target = 1.0;
+    }
+
+    pub fn close(&mut self, x: i32, y: i32) {
+        if let Some(door) = self.doors.get_mut(&(x, y)) {
+            door.target = 0.0;
+        }
+    }
+
+    /// Reverses a door that's on its way, so spamming the key doesn't get it stuck.
+    pub fn toggle(&mut self, x: i32, y: i32) {
+        let door = self.doors.entry((x, y)).or_default();
+        door.target = if door.target > 0.0 { 0.0 } else { 1.0 };
+    }
+
+    /// Moves every door towards its target.
+    pub fn update(&mut self, seconds: f32) {
+        for door in self.doors.values_mut() {
+            let step = door.speed * seconds;
+            door.open = if door.open < door.target {
+                (door.open + step).min(door.target)
+            } else {
+                (door.open - step).max(door.target)
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn animates_towards_target() {
+        let mut doors = DoorStates::default();
+        assert_eq!(None, doors.get(1, 1));
+        doors.close(1, 1);
+        assert_eq!(None, doors.get(1, 1));
+
+        doors.set_style(1, 1, DoorStyle::Swing, 2.0);
+        doors.open(1, 1);
+        doors.update(0.25);
+        assert_eq!(0.5, doors.open_fraction(1, 1));
+        assert!(!doors.is_open(1, 1));
+
+        doors.update(1.0);
+        assert!(doors.is_open(1, 1));
+
+        doors.toggle(1, 1);
+        doors.update(0.25);
+        assert_eq!(0.5, doors.open_fraction(1, 1));
+        doors.toggle(1, 1);
+        doors.update(0.25);
+        assert_eq!(1.0, doors.open_fraction(1, 1));
+    }
+}
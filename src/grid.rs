@@ -0,0 +1,233 @@
+//! Read access to the cell and material grids, either dense or split into chunks.
+//!
+//! A dense grid is the plain `width * height` array JS hands over. A chunked grid only keeps
+//! the chunks that were loaded, every other cell reads as the default value, so a mostly empty
+//! city doesn't need a u64 per cell.
+
+use crate::cell::CellMaterial;
+use crate::helpers::get_grid_value;
+
+#[derive(Clone, Copy)]
+pub enum MapGrid<'a> {
+    Dense {
+        data: &'a [u64],
+        width: usize,
+        height: usize,
+    },
+    Chunked(&'a ChunkedGrid),
+}
+
+impl MapGrid<'_> {
+    pub const EMPTY: MapGrid<'static> = MapGrid::Dense {
+        data: &[],
+        width: 0,
+        height: 0,
+    };
+
+    /// The value at (x, y), 0 outside of a dense grid and the default outside of a chunked one.
+    #[inline(always)]
+    pub fn get(&self, x: i32, y: i32) -> u64 {
+        match self {
+            MapGrid::Dense {
+                data,
+                width,
+                height,
+            } => get_grid_value(x, y, *width as i32, *height as i32, data),
+            MapGrid::Chunked(grid) => grid.get(x, y),
+        }
+    }
+
+    /// Whether (x, y) can be told apart from the default value: every cell of a dense grid
+    /// can, a cell of a chunked one only while its chunk is loaded.
+    pub fn is_loaded(&self, x: i32, y: i32) -> bool {
+        match self {
+            MapGrid::Dense { .. } => true,
+            MapGrid::Chunked(grid) => grid.is_loaded_at(x, y),
+        }
+    }
+
+    /// For a material grid, the height of its tallest wall, which nothing can be seen past.
+    pub fn tallest_wall(&self) -> f32 {
+        let height = |value: u64| CellMaterial::from_u64(value).tallest_wall();
+
+        match self {
+            MapGrid::Dense { data, .. } => {
+                data.iter().map(|value| height(*value)).fold(1.0, f32::max)
+            }
+            MapGrid::Chunked(grid) => grid.values().map(height).fold(1.0, f32::max),
+        }
+    }
+}
+
+pub struct ChunkedGrid {
+    width: usize,
+    height: usize,
+    chunk_shift: u32,
+    chunks_wide: usize,
+    chunks_high: usize,
+    default_value: u64,
+    chunks: Vec<Option<Box<[u64]>>>, // row by row, None for chunks that aren't loaded
+}
+
+impl ChunkedGrid {
+    /// `chunk_size` is rounded up to a power of two, so finding a chunk is a shift.
+    pub fn new(width: usize, height: usize, chunk_size: usize, default_value: u64) -> Self {
+        let chunk_size = chunk_size.max(1).next_power_of_two();
+        let chunks_wide = width.div_ceil(chunk_size);
+        let chunks_high = height.div_ceil(chunk_size);
+
+        Self {
+            width,
+            height,
+            chunk_shift: chunk_size.trailing_zeros(),
+            chunks_wide,
+            chunks_high,
+            default_value,
+            chunks: vec![None; chunks_wide * chunks_high],
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        1 << self.chunk_shift
+    }
+
+    #[inline(always)]
+    pub fn get(&self, x: i32, y: i32) -> u64 {
+        if !self.contains(x, y) {
+            return self.default_value;
+        }
+
+        let (chunk_x, chunk_y) = (
+            x as usize >> self.chunk_shift,
+            y as usize >> self.chunk_shift,
+        );
+        let Some(chunk) = self
+            .chunk_index(chunk_x, chunk_y)
+            .and_then(|idx| self.chunks[idx].as_ref())
+        else {
+            return self.default_value;
+        };
+
+        let mask = self.chunk_size() - 1;
+        chunk[((y as usize & mask) << self.chunk_shift) + (x as usize & mask)]
+    }
+
+    /// Returns false if the cell is in a chunk that isn't loaded, or outside of the grid.
+    pub fn set(&mut self, x: i32, y: i32, value: u64) -> bool {
+        if !self.contains(x, y) {
+            return false;
+        }
+
+        let (chunk_x, chunk_y) = (
+            x as usize >> self.chunk_shift,
+            y as usize >> self.chunk_shift,
+        );
+        let mask = self.chunk_size() - 1;
+        let idx = ((y as usize & mask) << self.chunk_shift) + (x as usize & mask);
+        match self
+            .chunk_index(chunk_x, chunk_y)
+            .and_then(|chunk| self.chunks[chunk].as_mut())
+        {
+            Some(chunk) => {
+                chunk[idx] = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Copies in the cells of a chunk row by row, missing ones get the default value.
+    /// Returns false if the chunk is outside of the grid.
+    pub fn load_chunk(&mut self, chunk_x: usize, chunk_y: usize, data: &[u64]) -> bool {
+        let Some(idx) = self.chunk_index(chunk_x, chunk_y) else {
+            return false;
+        };
+
+        let mut chunk = vec![self.default_value; self.chunk_size() * self.chunk_size()];
+        let len = chunk.len().min(data.len());
+        chunk[..len].copy_from_slice(&data[..len]);
+        self.chunks[idx] = Some(chunk.into_boxed_slice());
+
+        true
+    }
+
+    /// Returns false if the chunk wasn't loaded.
+    pub fn unload_chunk(&mut self, chunk_x: usize, chunk_y: usize) -> bool {
+        self.chunk_index(chunk_x, chunk_y)
+            .and_then(|idx| self.chunks[idx].take())
+            .is_some()
+    }
+
+    pub fn is_chunk_loaded(&self, chunk_x: usize, chunk_y: usize) -> bool {
+        self.chunk_index(chunk_x, chunk_y)
+            .is_some_and(|idx| self.chunks[idx].is_some())
+    }
+
+    /// Whether (x, y) is in the grid and in a loaded chunk, so `set` can change it.
+    pub fn is_loaded_at(&self, x: i32, y: i32) -> bool {
+        self.contains(x, y)
+            && self.is_chunk_loaded(
+                x as usize >> self.chunk_shift,
+                y as usize >> self.chunk_shift,
+            )
+    }
+
+    /// Every value of the loaded chunks, and the default one.
+    fn values(&self) -> impl Iterator<Item = u64> + '_ {
+        std::iter::once(self.default_value).chain(
+            self.chunks
+                .iter()
+                .flatten()
+                .flat_map(|chunk| chunk.iter().copied()),
+        )
+    }
+
+    // the last chunks of a row or column may reach past the grid, those cells aren't in it
+    #[inline(always)]
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+
+    #[inline(always)]
+    fn chunk_index(&self, chunk_x: usize, chunk_y: usize) -> Option<usize> {
+        (chunk_x < self.chunks_wide && chunk_y < self.chunks_high)
+            .then(|| chunk_y * self.chunks_wide + chunk_x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_loaded_chunks_and_defaults() {
+        let mut grid = ChunkedGrid::new(10, 10, 3, 0x8);
+        assert_eq!(4, grid.chunk_size());
+
+        let chunk: Vec<u64> = (0..16).collect();
+        assert!(grid.load_chunk(1, 2, &chunk));
+        assert!(!grid.load_chunk(3, 0, &chunk));
+
+        assert_eq!(0, grid.get(4, 8));
+        assert_eq!(7, grid.get(7, 9));
+        assert_eq!(0x8, grid.get(3, 8));
+        assert_eq!(0x8, grid.get(-1, 8));
+        assert_eq!(0x8, grid.get(400, 8));
+
+        assert!(grid.set(7, 9, 0x1));
+        assert!(!grid.set(0, 0, 0x1));
+        assert_eq!(0x1, grid.get(7, 9));
+
+        // the chunk reaches past the 10 by 10 grid, which ends where a dense one would
+        assert!(grid.load_chunk(2, 2, &[0x1; 16]));
+        assert_eq!(0x1, grid.get(9, 9));
+        assert_eq!(0x8, grid.get(10, 9));
+        assert_eq!(0x8, grid.get(9, 11));
+        assert!(!grid.set(10, 9, 0x1));
+
+        assert!(grid.is_loaded_at(7, 9) && !grid.is_loaded_at(3, 9) && !grid.is_loaded_at(10, 9));
+        assert!(grid.unload_chunk(1, 2));
+        assert!(!grid.is_chunk_loaded(1, 2));
+        assert_eq!(0x8, grid.get(7, 9));
+    }
+}
@@ -73,6 +73,22 @@ impl WasmTextureMap {
     pub fn get_map(&self) -> &HashMap<(i32, i32), Vec<u8>> {
         &self.map
     }
+
+    /// Angle 0 textures indexed by texture id (0-255), for lookups without hashing.
+    pub fn texture_lookup(&self, meta_map: &WasmTextureMetaMap) -> Vec<Option<Texture<'_>>> {
+        (0..TEXTURE_LOOKUP_SIZE)
+            .map(|id| {
+                let meta = meta_map.get(id)?;
+                let data = self.map.get(&(id, 0))?;
+
+                Some(Texture {
+                    data,
+                    width: meta.width,
+                    height: meta.height,
+                })
+            })
+            .collect()
+    }
 }
 
 #[wasm_bindgen]
@@ -134,10 +150,16 @@ impl WasmStripePerCoordMap {
                 dy: 0.,
                 angle: angle as i32,
                 height: height as i32,
+                lift: 0.,
                 r#type: sprite_type as i32,
+                is_wall: false,
                 column: 0,
                 distance: 0.,
                 distance_fixed: 0,
+                index: (i / 5) as u32,
+                bounces: 0,
+                portals: 0,
+                entry_distance: 0.,
             });
         }
 
@@ -333,8 +355,30 @@ pub struct Position {
     pub plane_x: f32,
     pub plane_y: f32,
     pub pitch: i32,
-    pub z: i32,
-    pub plane_y_initial: f32,
+    pub z: f32,               // eye height above the floor, in wall heights
+    pub plane_y_initial: f32, // length of the plane, the zbuffer is in these; see `Camera`
+}
+
+#[wasm_bindgen]
+impl Position {
+    /// A single ray from (x, y) along (dir_x, dir_y) with eyes at height `z`, without a camera
+    /// plane, e.g. for `castRaySurfaces`. Any column looks straight along it.
+    #[wasm_bindgen(js_name = lookingAlong)]
+    pub fn looking_along(x: f32, y: f32, dir_x: f32, dir_y: f32, z: f32) -> Position {
+        Position {
+            x,
+            y,
+            map_x: x.floor() as i32,
+            map_y: y.floor() as i32,
+            dir_x,
+            dir_y,
+            plane_x: 0.0,
+            plane_y: 0.0,
+            pitch: 0,
+            z,
+            plane_y_initial: 1.0,
+        }
+    }
 }
 
 // Data structures
@@ -354,10 +398,23 @@ pub struct Sprite {
     pub dy: f32,
     pub angle: i32,
     pub height: i32,
-    pub r#type: i32,
+    pub lift: f32, // walls only, how far it's raised in its heights, e.g. a door sliding up
+    pub r#type: i32, // texture id
+    pub is_wall: bool, // a single column of a wall hit, rather than a billboard
     pub column: u32,
     pub distance: f32,
     pub distance_fixed: i32,
+    pub index: u32, // position in the sprite data, so a picked sprite can be told apart
+    pub bounces: u8, // mirrors it's seen in, drawn where it appears to be behind them
+    pub portals: u8, // portals it's seen through, likewise
+    pub entry_distance: f32, // to the first of those mirrors or portals, it's drawn right after it
+}
+
+impl Sprite {
+    #[inline(always)]
+    pub fn is_seen_through(&self) -> bool {
+        self.bounces > 0 || self.portals > 0
+    }
 }
 
 #[wasm_bindgen]
@@ -371,10 +428,14 @@ pub struct TranslationResult {
 #[derive(Serialize, Clone, Copy)]
 pub struct SpritePart<'a> {
     pub sprite_type: i32, // TODO: u8?
+    pub opacity: i32,     // fixed point, fades out towards the view distance
     pub sprite_left_x: u32,
     pub width: i32,
     pub screen_y_ceiling: i32,
     pub height: i32,
+    pub hidden_rows: i32, // rows cut off the top, the texture starts that far in
+    pub tint: u32,        // RGB the texels are blended towards, for reflections
+    pub tint_strength: i32, // out of 256
     pub tex_x1: i32,
     pub tex_width: i32,
     pub alpha: i32,
@@ -392,8 +453,14 @@ pub fn has_bit_set(value: u64, bit: u8) -> bool {
 }
 
 #[inline(always)]
-pub fn get_grid_value(map_x: i32, map_y: i32, map_width: i32, map_data: &[u64]) -> u64 {
-    if map_x < 0 || map_y < 0 || map_x >= map_width || map_y >= map_width {
+pub fn get_grid_value(
+    map_x: i32,
+    map_y: i32,
+    map_width: i32,
+    map_height: i32,
+    map_data: &[u64],
+) -> u64 {
+    if map_x < 0 || map_y < 0 || map_x >= map_width || map_y >= map_height {
         return 0;
     }
 
@@ -410,6 +477,9 @@ pub fn get_bits(value: u64, start_bit: u8) -> u8 {
     ((value >> start_bit) & 0b1111) as u8
 }
 
+pub const TEXTURE_LOOKUP_SIZE: i32 = 256;
+
+#[derive(Clone, Copy)]
 pub struct Texture<'a> {
     pub data: &'a [u8],
     pub width: i32,
@@ -431,6 +501,17 @@ pub fn to_fixed_large(f: f32) -> i32 {
     (f * (FIXED_ONE_LARGE as f32)) as i32
 }
 
+/// The last share of the view distance over which walls, sprites and floors fade out
+/// into whatever is behind them, usually the sky.
+pub const FADE_SHARE: f32 = 0.2;
+
+/// Opacity in fixed point, fully opaque until the fade starts and 0 at `max_distance`.
+#[inline(always)]
+pub fn distance_opacity(distance: f32, max_distance: f32) -> i32 {
+    let fade_length = (max_distance * FADE_SHARE).max(f32::EPSILON);
+    to_fixed(((max_distance - distance) / fade_length).clamp(0.0, 1.0))
+}
+
 #[inline(always)]
 pub fn fixed_mul(a: i32, b: i32) -> i32 {
     ((a as i64 * b as i64) >> FIXED_SHIFT) as i32
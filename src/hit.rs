@@ -0,0 +1,135 @@
+//! What a ray ran into, as returned by `raycast_column`: the hit that stopped it and every
+//! surface along the way.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+/// The values match the hit types the renderer used before, so `Wall as i32 == 1`.
+#[wasm_bindgen]
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HitKind {
+    Wall = 1,
+    Door = 2,
+    Window = 3,
+    Mirror = 4,
+    Portal = 5,
+}
+
+#[wasm_bindgen]
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// Where the ray hit, in map coordinates.
+    pub x: f32,
+    pub y: f32,
+    /// Distance along the view direction, in lengths of the direction vector (no fisheye).
+    pub perp_distance: f32,
+    /// Straight line distance from the camera.
+    pub distance: f32,
+    pub map_x: i32,
+    pub map_y: i32,
+    /// Index of the wall segment in the cell, none for solid cells.
+    pub segment: Option<u8>,
+    /// 0 if the hit face runs north to south, 1 if it runs east to west, like the
+    /// sides of the DDA; the nearest of the two for walls at an angle and pillars.
+    pub side: u8,
+    /// Unit normal of the hit face, pointing back towards the camera.
+    pub normal_x: f32,
+    pub normal_y: f32,
+    /// Texture id, with the default texture of the kind filled in.
+    pub texture: i32,
+    /// Horizontal texture coordinate, 0 to 1.
+    pub u: f32,
+    /// Height of the wall, in wall heights.
+    pub height: f32,
+    /// How far the surface is raised off the floor, in its heights, e.g. a door sliding up.
+    pub lift: f32,
+    pub kind: HitKind,
+    /// Mirrors the ray bounced off before the hit; the distances are along the whole path.
+    pub bounces: u8,
+    /// Portals the ray went through before the hit, likewise.
+    pub portals: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii::AsciiMap;
+    use crate::TestRay;
+
+    fn cast(map: &AsciiMap, x: f32, y: f32, dir_x: f32, dir_y: f32) -> Option<RayHit> {
+        TestRay::new(map.grid()).cast(x, y, dir_x, dir_y).ray_hit
+    }
+
+    #[test]
+    fn describes_what_rays_hit() {
+        let map = AsciiMap::parse(
+            "
+            ###
+            #.#
+            #D#
+            #.#
+            ###
+            ",
+        )
+        .unwrap();
+
+        let door = cast(&map, 1.25, 1.5, 0.0, 1.0).unwrap();
+        assert_eq!(HitKind::Door, door.kind);
+        assert_eq!(
+            (1, 2, Some(0), 1),
+            (door.map_x, door.map_y, door.segment, door.side)
+        );
+        assert_eq!((0.0, -1.0), (door.normal_x, door.normal_y));
+        assert!((door.perp_distance - 0.5).abs() < 1e-4);
+        assert!((door.u - 0.25).abs() < 1e-4);
+
+        let wall = cast(&map, 1.25, 1.5, 0.0, -1.0).unwrap();
+        assert_eq!(HitKind::Wall, wall.kind);
+        assert_eq!(
+            (1, 0, None, 1),
+            (wall.map_x, wall.map_y, wall.segment, wall.side)
+        );
+        assert_eq!((0.0, 1.0), (wall.normal_x, wall.normal_y));
+        assert!((wall.y - 1.0).abs() < 1e-4 && (wall.distance - 0.5).abs() < 1e-4);
+        assert!((wall.u - 0.25).abs() < 1e-4);
+    }
+
+    #[test]
+    fn collects_surfaces_nearest_first() {
+        let map = AsciiMap::parse(
+            "
+            #######
+            #.W.W.#
+            #######
+            ",
+        )
+        .unwrap();
+        let surfaces = |max_surfaces| {
+            TestRay {
+                max_surfaces,
+                stop_at_first_hit: false,
+                ..TestRay::new(map.grid())
+            }
+            .cast(1.5, 1.5, 1.0, 0.0)
+            .surfaces
+        };
+
+        let all = surfaces(8);
+        assert_eq!(
+            vec![
+                (HitKind::Window, 2),
+                (HitKind::Window, 4),
+                (HitKind::Wall, 6)
+            ],
+            all.iter()
+                .map(|hit| (hit.kind, hit.map_x))
+                .collect::<Vec<_>>()
+        );
+        assert!((all[1].perp_distance - 2.5).abs() < 1e-4);
+        assert!((all[2].perp_distance - 4.5).abs() < 1e-4);
+        // the wall the ray stops at is kept over the windows in front of it
+        assert_eq!(vec![all[0], all[2]], surfaces(2).into_vec());
+        assert_eq!(vec![all[2]], surfaces(1).into_vec());
+        assert!(surfaces(0).is_empty());
+    }
+}
@@ -0,0 +1,588 @@
+//! Stacked map layers for multi-storey levels.
+//!
+//! Every layer is a full grid (plus its material layer) of the same size, placed at a floor height
+//! measured in wall heights. The camera stands on the highest layer whose floor is at or below its
+//! feet and sees the other layers through cells of its own layer without a floor or ceiling, like
+//! stairwells, or through windows.
+//!
+//! A layer either points into dense arrays owned by JS or keeps its cells in chunks, which are
+//! loaded and unloaded at runtime; see `ChunkedGrid`.
+//!
+//! Each layer also keeps the open state of its doors, the walls being pushed across it and the
+//! links of its portals, see `DoorStates`, `PushWalls` and `Portals`.
+
+use std::slice::from_raw_parts;
+
+use wasm_bindgen::prelude::*;
+
+use crate::cell::{Cell, CellMaterial};
+use crate::doors::{DoorStates, DoorStyle};
+use crate::grid::{ChunkedGrid, MapGrid};
+use crate::portals::{PortalEnd, Portals};
+use crate::push_walls::{Push, PushWall, PushWalls};
+
+// how far below a floor the feet can be and still stand on it, so rounding doesn't drop a layer
+const FLOOR_TOLERANCE: f32 = 0.01;
+
+static NO_DOORS: DoorStates = DoorStates::EMPTY;
+static NO_PUSH_WALLS: PushWalls = PushWalls::EMPTY;
+static NO_PORTALS: Portals = Portals::EMPTY;
+
+enum LayerStorage {
+    Dense {
+        cells: *mut u64,
+        materials: *mut u64,
+    },
+    Chunked {
+        cells: ChunkedGrid,
+        materials: ChunkedGrid,
+    },
+}
+
+struct MapLayer {
+    storage: LayerStorage,
+    floor_height: f32,
+    doors: DoorStates,
+    push_walls: PushWalls,
+    portals: Portals,
+    tallest_wall: f32, // of its materials, kept up as they change; see `MapGrid::tallest_wall`
+}
+
+#[wasm_bindgen]
+pub struct WasmMapLayers {
+    width: usize,
+    height: usize,
+    layers: Vec<MapLayer>, // sorted by floor height
+}
+
+#[wasm_bindgen]
+impl WasmMapLayers {
+    #[wasm_bindgen(constructor)]
+    pub fn new(map_width: usize, map_height: usize) -> Self {
+        Self {
+            width: map_width,
+            height: map_height,
+            layers: vec![],
+        }
+    }
+
+    /// Both arrays hold `map_width * map_height` cells and must outlive the layers, e.g. the
+    /// `ptr` of a `WasmUInt64Array`. The material array may be null.
+    #[wasm_bindgen(js_name = addLayer)]
+    pub fn add_layer(
+        &mut self,
+        map_array: *mut u64,
+        material_array: *mut u64,
+        floor_height: f32,
+    ) -> usize {
+        let tallest_wall = self.dense(material_array).tallest_wall();
+        self.insert(MapLayer {
+            storage: LayerStorage::Dense {
+                cells: map_array,
+                materials: material_array,
+            },
+            floor_height,
+            doors: DoorStates::default(),
+            push_walls: PushWalls::default(),
+            portals: Portals::default(),
+            tallest_wall,
+        })
+    }
+
+    /// A layer without any chunks loaded, where every cell is `default_cell` with the
+    /// `default_material`. `chunk_size` is rounded up to a power of two. Returns the index of
+    /// the layer, which changes if a lower layer is added later.
+    #[wasm_bindgen(js_name = addChunkedLayer)]
+    pub fn add_chunked_layer(
+        &mut self,
+        chunk_size: usize,
+        default_cell: u64,
+        default_material: u64,
+        floor_height: f32,
+    ) -> usize {
+        self.insert(MapLayer {
+            storage: LayerStorage::Chunked {
+                cells: ChunkedGrid::new(self.width, self.height, chunk_size, default_cell),
+                materials: ChunkedGrid::new(self.width, self.height, chunk_size, default_material),
+            },
+            floor_height,
+            doors: DoorStates::default(),
+            push_walls: PushWalls::default(),
+            portals: Portals::default(),
+            tallest_wall: CellMaterial::from_u64(default_material).tallest_wall(),
+        })
+    }
+
+    /// Call after writing to the material array of a dense layer, so rays look past walls as
+    /// tall as the new ones.
+    #[wasm_bindgen(js_name = refreshMaterials)]
+    pub fn refresh_materials(&mut self, index: usize) {
+        let tallest_wall = self.materials(index).tallest_wall();
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.tallest_wall = tallest_wall;
+        }
+    }
+
+    /// Copies in the cells and materials of a chunk, row by row. Returns false if the layer
+    /// isn't chunked or the chunk is outside of the map.
+    #[wasm_bindgen(js_name = loadChunk)]
+    pub fn load_chunk(
+        &mut self,
+        index: usize,
+        chunk_x: usize,
+        chunk_y: usize,
+        cells: &[u64],
+        materials: &[u64],
+    ) -> bool {
+        let Some(layer) = self.layers.get_mut(index) else {
+            return false;
+        };
+        let LayerStorage::Chunked {
+            cells: cell_grid,
+            materials: material_grid,
+        } = &mut layer.storage
+        else {
+            return false;
+        };
+        if !(cell_grid.load_chunk(chunk_x, chunk_y, cells)
+            && material_grid.load_chunk(chunk_x, chunk_y, materials))
+        {
+            return false;
+        }
+
+        layer.tallest_wall = materials
+            .iter()
+            .map(|material| CellMaterial::from_u64(*material).tallest_wall())
+            .fold(layer.tallest_wall, f32::max);
+        true
+    }
+
+    /// Returns false if the chunk wasn't loaded.
+    #[wasm_bindgen(js_name = unloadChunk)]
+    pub fn unload_chunk(&mut self, index: usize, chunk_x: usize, chunk_y: usize) -> bool {
+        let Some(layer) = self.layers.get_mut(index) else {
+            return false;
+        };
+        let LayerStorage::Chunked { cells, materials } = &mut layer.storage else {
+            return false;
+        };
+        materials.unload_chunk(chunk_x, chunk_y);
+        let unloaded = cells.unload_chunk(chunk_x, chunk_y);
+        // what's left loaded, which is a fraction of the map
+        layer.tallest_wall = MapGrid::Chunked(materials).tallest_wall();
+        unloaded
+    }
+
+    #[wasm_bindgen(js_name = isChunkLoaded)]
+    pub fn is_chunk_loaded(&self, index: usize, chunk_x: usize, chunk_y: usize) -> bool {
+        match self.layers.get(index).map(|layer| &layer.storage) {
+            Some(LayerStorage::Chunked { cells, .. }) => cells.is_chunk_loaded(chunk_x, chunk_y),
+            _ => false,
+        }
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[wasm_bindgen]
+    pub fn count_layers(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Index of the layer something at `elevation` (in wall heights) stands on.
+    #[wasm_bindgen(js_name = layerAt)]
+    pub fn layer_at(&self, elevation: f32) -> usize {
+        self.layers
+            .partition_point(|layer| layer.floor_height <= elevation + FLOOR_TOLERANCE)
+            .saturating_sub(1)
+    }
+
+    #[wasm_bindgen(js_name = floorHeight)]
+    pub fn floor_height(&self, index: usize) -> f32 {
+        self.layers
+            .get(index)
+            .map_or(0.0, |layer| layer.floor_height)
+    }
+    /// How the door in cell (x, y) of a layer opens and how many times per second, e.g. 2 to
+    /// open in half a second. Doors that aren't set up slide left in a second.
+    #[wasm_bindgen(js_name = setDoor)]
+    pub fn set_door(&mut self, index: usize, x: i32, y: i32, style: DoorStyle, speed: f32) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.doors.set_style(x, y, style, speed);
+        }
+    }
+
+    /// Starts opening the door, `updateDoors` moves it.
+    #[wasm_bindgen(js_name = openDoor)]
+    pub fn open_door(&mut self, index: usize, x: i32, y: i32) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.doors.open(x, y);
+        }
+    }
+
+    #[wasm_bindgen(js_name = closeDoor)]
+    pub fn close_door(&mut self, index: usize, x: i32, y: i32) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.doors.close(x, y);
+        }
+    }
+
+    #[wasm_bindgen(js_name = toggleDoor)]
+    pub fn toggle_door(&mut self, index: usize, x: i32, y: i32) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.doors.toggle(x, y);
+        }
+    }
+
+    /// 0 for closed doors, 1 for open ones.
+    #[wasm_bindgen(js_name = doorOpenFraction)]
+    pub fn door_open_fraction(&self, index: usize, x: i32, y: i32) -> f32 {
+        self.doors(index).open_fraction(x, y)
+    }
+
+    /// Starts pushing the solid cell (x, y) of a layer as `push` says. Returns false if it's not
+    /// a solid cell, it's already moving or there's a wall in the way. Cells in chunks that
+    /// aren't loaded are in the way.
+    #[wasm_bindgen(js_name = pushWall)]
+    pub fn push_wall(&mut self, index: usize, x: i32, y: i32, push: Push) -> bool {
+        let Push {
+            dir_x,
+            dir_y,
+            cells,
+            speed,
+        } = push;
+        if dir_x.abs() + dir_y.abs() != 1 || cells < 1 || speed <= 0.0 {
+            return false;
+        }
+        let grid = self.cells(index);
+        let wall = PushWall {
+            x,
+            y,
+            dir_x,
+            dir_y,
+            cells,
+            speed,
+            offset: 0.0,
+            cell: grid.get(x, y),
+            material: self.materials(index).get(x, y),
+        };
+        let is_in_bounds = |(x, y): (i32, i32)| {
+            x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+        };
+        let is_free = |(x, y): (i32, i32)| {
+            is_in_bounds((x, y))
+                && grid.is_loaded(x, y)
+                && !Cell::from_u64(grid.get(x, y)).has_wall
+                && !self.push_walls(index).is_taken(x, y)
+        };
+        if !is_in_bounds((x, y))
+            || !grid.is_loaded(x, y)
+            || !Cell::from_u64(wall.cell).is_solid()
+            || self.push_walls(index).is_taken(x, y)
+            || !wall.path().all(is_free)
+        {
+            return false;
+        }
+
+        // the cell it leaves shows the floor of the one it'll cover
+        let (to_x, to_y) = wall.destination();
+        let (uncovered_cell, uncovered_material) =
+            (grid.get(to_x, to_y), self.materials(index).get(to_x, to_y));
+        let width = self.width;
+        let Some(layer) = self.layers.get_mut(index) else {
+            return false;
+        };
+        layer.set(width, x, y, uncovered_cell, uncovered_material);
+        layer.push_walls.start(wall);
+
+        true
+    }
+
+    /// Moves the walls being pushed on every layer and puts the ones that arrived into the map,
+    /// call it once a frame. A wall arriving in a chunk unloaded since it started waits there
+    /// until the chunk is loaded again.
+    #[wasm_bindgen(js_name = updatePushWalls)]
+    pub fn update_push_walls(&mut self, seconds: f32) {
+        let width = self.width;
+        for layer in self.layers.iter_mut() {
+            for wall in layer.push_walls.update(seconds) {
+                let (x, y) = wall.destination();
+                if !layer.set(width, x, y, wall.cell, wall.material) {
+                    layer.push_walls.start(wall);
+                }
+            }
+        }
+    }
+
+    /// Links two segments of a layer both ways: what's behind either is seen through the other
+    /// and walking into one comes out of the other. With `reversed` one comes out on the side one
+    /// went in from, turned around. Pillars can't be portals and the segments should be of the
+    /// same length.
+    #[wasm_bindgen(js_name = linkPortal)]
+    pub fn link_portal(&mut self, index: usize, from: PortalEnd, to: PortalEnd, reversed: bool) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.portals.link(from.into(), to.into(), reversed);
+        }
+    }
+
+    /// Turns a portal and the segment it leads to back into plain walls.
+    #[wasm_bindgen(js_name = unlinkPortal)]
+    pub fn unlink_portal(&mut self, index: usize, end: PortalEnd) {
+        if let Some(layer) = self.layers.get_mut(index) {
+            layer.portals.unlink(end.into());
+        }
+    }
+
+    /// Moves the doors of every layer, call it once a frame.
+    #[wasm_bindgen(js_name = updateDoors)]
+    pub fn update_doors(&mut self, seconds: f32) {
+        for layer in self.layers.iter_mut() {
+            layer.doors.update(seconds);
+        }
+    }
+}
+
+impl MapLayer {
+    // the cell is in the map, checked by the callers. False if its chunk isn't loaded.
+    fn set(&mut self, width: usize, x: i32, y: i32, cell: u64, material: u64) -> bool {
+        match &mut self.storage {
+            LayerStorage::Dense { cells, materials } => {
+                let idx = y as usize * width + x as usize;
+                unsafe {
+                    if !cells.is_null() {
+                        *cells.add(idx) = cell;
+                    }
+                    if !materials.is_null() {
+                        *materials.add(idx) = material;
+                    }
+                }
+            }
+            LayerStorage::Chunked { cells, materials } => {
+                // the same chunk of both
+                if !(cells.set(x, y, cell) && materials.set(x, y, material)) {
+                    return false;
+                }
+            }
+        }
+        self.tallest_wall = self
+            .tallest_wall
+            .max(CellMaterial::from_u64(material).tallest_wall());
+        true
+    }
+}
+
+// 🦀 Rust-only implementation block
+impl WasmMapLayers {
+    /// The grid of a layer, reading as empty cells if there's no such layer.
+    pub fn cells(&self, index: usize) -> MapGrid<'_> {
+        match self.layers.get(index).map(|layer| &layer.storage) {
+            Some(LayerStorage::Dense { cells, .. }) => self.dense(*cells),
+            Some(LayerStorage::Chunked { cells, .. }) => MapGrid::Chunked(cells),
+            None => self.dense(std::ptr::null()),
+        }
+    }
+
+    /// The material layer of a layer, reading as default materials if there's none.
+    pub fn materials(&self, index: usize) -> MapGrid<'_> {
+        match self.layers.get(index).map(|layer| &layer.storage) {
+            Some(LayerStorage::Dense { materials, .. }) => self.dense(*materials),
+            Some(LayerStorage::Chunked { materials, .. }) => MapGrid::Chunked(materials),
+            None => self.dense(std::ptr::null()),
+        }
+    }
+
+    /// The height of the tallest wall of a layer, at least a full wall, without going over its
+    /// materials.
+    pub fn tallest_wall(&self, index: usize) -> f32 {
+        self.layers
+            .get(index)
+            .map_or(1.0, |layer| layer.tallest_wall)
+    }
+
+    /// The doors of a layer, all closed if there's no such layer.
+    pub fn doors(&self, index: usize) -> &DoorStates {
+        self.layers
+            .get(index)
+            .map_or(&NO_DOORS, |layer| &layer.doors)
+    }
+
+    /// The walls being pushed across a layer, none if there's no such layer.
+    pub fn push_walls(&self, index: usize) -> &PushWalls {
+        self.layers
+            .get(index)
+            .map_or(&NO_PUSH_WALLS, |layer| &layer.push_walls)
+    }
+
+    /// The portals of a layer, none if there's no such layer.
+    pub fn portals(&self, index: usize) -> &Portals {
+        self.layers
+            .get(index)
+            .map_or(&NO_PORTALS, |layer| &layer.portals)
+    }
+
+    fn dense(&self, ptr: *const u64) -> MapGrid<'_> {
+        let data = if ptr.is_null() {
+            &[]
+        } else {
+            unsafe { from_raw_parts(ptr, self.width * self.height) }
+        };
+
+        MapGrid::Dense {
+            data,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn insert(&mut self, layer: MapLayer) -> usize {
+        let index = self
+            .layers
+            .partition_point(|other| other.floor_height <= layer.floor_height);
+        self.layers.insert(index, layer);
+        index
+    }
+
+    /// The order layers are drawn in when standing on `current`: layers below from the bottom up,
+    /// then layers above from the top down, then the current one. Each layer only draws its
+    /// floors where it has them, so nearer layers cover farther ones except through openings.
+    pub fn draw_order(&self, current: usize) -> impl Iterator<Item = usize> {
+        let count = self.layers.len();
+        let current = current.min(count.saturating_sub(1));
+
+        (0..current)
+            .chain((current + 1..count).rev())
+            .chain((count > 0).then_some(current))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii::AsciiMap;
+
+    #[test]
+    fn picks_layer_and_draw_order() {
+        let mut ground = vec![1u64; 4];
+        let mut first = vec![2u64; 4];
+        let mut second = vec![3u64; 4];
+
+        let mut layers = WasmMapLayers::new(2, 2);
+        layers.add_layer(second.as_mut_ptr(), std::ptr::null_mut(), 2.0);
+        layers.add_layer(ground.as_mut_ptr(), std::ptr::null_mut(), 0.0);
+        layers.add_layer(first.as_mut_ptr(), std::ptr::null_mut(), 1.0);
+
+        assert_eq!(0, layers.layer_at(-0.5));
+        assert_eq!(0, layers.layer_at(0.3));
+        assert_eq!(1, layers.layer_at(0.995));
+        assert_eq!(2, layers.layer_at(5.0));
+
+        assert_eq!(2, layers.cells(1).get(1, 1));
+        assert_eq!(0, layers.materials(1).get(1, 1));
+        assert_eq!(0, layers.cells(3).get(1, 1));
+
+        assert_eq!(vec![0, 2, 1], layers.draw_order(1).collect::<Vec<_>>());
+        assert_eq!(vec![2, 1, 0], layers.draw_order(0).collect::<Vec<_>>());
+        assert_eq!(0, WasmMapLayers::new(1, 1).draw_order(0).count());
+    }
+
+    #[test]
+    fn loads_chunks_of_chunked_layers() {
+        let mut ground = vec![1u64; 4];
+        let mut layers = WasmMapLayers::new(1024, 1024);
+        layers.add_layer(ground.as_mut_ptr(), std::ptr::null_mut(), 0.0);
+        let index = layers.add_chunked_layer(16, 0x8, 0, 1.0);
+
+        assert!(!layers.load_chunk(0, 0, 0, &[0x1], &[]));
+        assert!(layers.load_chunk(index, 2, 3, &[0x1], &[0x300]));
+        assert!(layers.is_chunk_loaded(index, 2, 3));
+        assert_eq!(0x1, layers.cells(index).get(32, 48));
+        assert_eq!(0x300, layers.materials(index).get(32, 48));
+        assert_eq!(0x8, layers.cells(index).get(33, 48));
+
+        assert!(layers.unload_chunk(index, 2, 3));
+        assert_eq!(0x8, layers.cells(index).get(32, 48));
+    }
+
+    #[test]
+    fn keeps_up_the_tallest_wall_of_chunked_layers() {
+        let mut tall = CellMaterial::new();
+        tall.set_wall_height(0, 2.0);
+        let mut layers = WasmMapLayers::new(64, 64);
+        let index = layers.add_chunked_layer(16, 0x8, 0, 0.0);
+        assert_eq!(1.0, layers.tallest_wall(index));
+
+        assert!(layers.load_chunk(index, 1, 1, &[0x1], &[tall.to_u64()]));
+        assert_eq!(2.0, layers.tallest_wall(index));
+        assert!(layers.unload_chunk(index, 1, 1));
+        assert_eq!(1.0, layers.tallest_wall(index));
+    }
+
+    #[test]
+    fn pushes_walls_into_the_map() {
+        let mut map = AsciiMap::parse(
+            "
+            ######
+            #.#..#
+            ######
+            ",
+        )
+        .unwrap();
+        let mut materials = vec![0u64; map.cells.len()];
+        materials[8] = 0x64;
+        let mut layers = WasmMapLayers::new(map.width, map.height);
+        layers.add_layer(map.cells.as_mut_ptr(), materials.as_mut_ptr(), 0.0);
+
+        assert!(!layers.push_wall(0, 1, 1, Push::new(1, 0, 1, 1.0))); // not a wall
+        assert!(!layers.push_wall(0, 2, 1, Push::new(1, 0, 3, 1.0))); // into the outer wall
+        assert!(layers.push_wall(0, 2, 1, Push::new(1, 0, 2, 1.0)));
+        assert!(!layers.push_wall(0, 2, 1, Push::new(1, 0, 1, 1.0)));
+        assert_eq!(0x2, layers.cells(0).get(2, 1));
+
+        layers.update_push_walls(0.5);
+        assert_eq!(0x2, layers.cells(0).get(4, 1)); // still on its way
+        layers.update_push_walls(1.5);
+        assert_eq!(
+            (0x2, 0x1),
+            (layers.cells(0).get(2, 1), layers.cells(0).get(4, 1))
+        );
+        assert_eq!(0x64, layers.materials(0).get(4, 1));
+    }
+
+    #[test]
+    fn pushes_walls_only_through_loaded_chunks() {
+        let map = AsciiMap::parse(
+            "
+            ....
+            .##.
+            ....
+            ....
+            ",
+        )
+        .unwrap();
+        let (wall, floor) = (map.cells[5], map.cells[0]);
+        let mut layers = WasmMapLayers::new(12, 4);
+        let index = layers.add_chunked_layer(4, floor, 0, 0.0);
+        assert!(layers.load_chunk(index, 0, 0, &map.cells, &[]));
+
+        // into the chunk to the right, which isn't loaded
+        assert!(!layers.push_wall(index, 2, 1, Push::new(1, 0, 2, 1.0)));
+        assert!(layers.load_chunk(index, 1, 0, &[floor; 16], &[]));
+        assert!(layers.push_wall(index, 2, 1, Push::new(1, 0, 2, 1.0)));
+
+        // unloaded on the way, the wall waits for it
+        assert!(layers.unload_chunk(index, 1, 0));
+        layers.update_push_walls(2.0);
+        assert!(!layers.push_wall(index, 1, 1, Push::new(1, 0, 1, 1.0)));
+        assert!(layers.load_chunk(index, 1, 0, &[floor; 16], &[]));
+        assert_eq!(floor, layers.cells(index).get(4, 1));
+        layers.update_push_walls(0.0);
+        assert_eq!(wall, layers.cells(index).get(4, 1));
+    }
+}
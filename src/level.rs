@@ -0,0 +1,636 @@
+//! Text level format, loaded from JSON into the grid and sprite map the renderer consumes.
+//!
+//! ```json
+//! {
+//!   "width": 3,
+//!   "height": 2,
+//!   "default": { "road": true },
+//!   "cells": [
+//!     { "x": 1, "y": 0, "ceiling": true, "walls": [{ "thickness": 1, "depth": 10, "north": true, "texture": 100 }] },
+//!     { "x": 2, "y": 0, "walls": [{ "offset": 5, "thickness": 1, "depth": 10, "height": 0.5 }] },
+//!     { "x": 2, "y": 1, "solid": true },
+//!     { "x": 1, "y": 1, "walls": [{ "from": [0, 0], "to": [10, 10] }] },
+//!     { "x": 0, "y": 0, "walls": [{ "centre": [5, 5], "radius": 3, "texture": 100 }] },
+//!     { "x": 0, "y": 1, "road": true, "floor_texture": 100 }
+//!   ],
+//!   "sprites": [{ "x": 1.5, "y": 0.5, "angle": 0, "height": 1.0, "type": 7 }],
+//!   "textures": [
+//!     { "id": 7, "src": "barrel1.png", "width": 355, "height": 438 },
+//!     { "id": 100, "src": "brick.png", "width": 512, "height": 512 }
+//!   ]
+//! }
+//! ```
+//!
+//! Wall offsets, thickness and depth are in tenths of a cell (0-10), exactly as in `Cell`.
+//! Walls at an angle are given by their `from` and `to` points instead, also in tenths, and
+//! round pillars by their `centre` and `radius`; a cell holds walls of one kind only.
+//! Texture ids refer to the `textures` bindings, 0 (or none) uses the default texture.
+//! Walls with `"mirror": true` reflect what's in front of them.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::value::RawValue;
+use std::fmt;
+use wasm_bindgen::prelude::*;
+
+use crate::cell::{Cell, CellGeometry, CellMaterial, WallSegment, MAX_WALL_SEGMENTS};
+use crate::helpers::WasmStripePerCoordMap;
+
+// 4096 by 4096, a u64 per cell for the grid and again for the materials is 256 MiB
+const MAX_CELLS: usize = 1 << 24;
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WallDef {
+    #[serde(deserialize_with = "tenths")]
+    pub offset: u8,
+    #[serde(deserialize_with = "tenths")]
+    pub thickness: u8,
+    #[serde(deserialize_with = "tenths")]
+    pub depth: u8,
+    #[serde(deserialize_with = "tenths")]
+    pub offset_secondary: u8,
+    pub north: bool,
+    pub door: bool,
+    pub window: bool,
+    pub mirror: bool,
+    pub texture: u8,
+    #[serde(default = "default_wall_height", deserialize_with = "wall_height")]
+    pub height: f32, // in wall heights, rounded to quarters
+    #[serde(deserialize_with = "point")]
+    pub from: Option<[u8; 2]>,
+    #[serde(deserialize_with = "point")]
+    pub to: Option<[u8; 2]>,
+    #[serde(deserialize_with = "point")]
+    pub centre: Option<[u8; 2]>,
+    #[serde(deserialize_with = "tenths")]
+    pub radius: u8,
+}
+
+impl WallDef {
+    fn geometry(&self) -> CellGeometry {
+        if self.centre.is_some() {
+            CellGeometry::Cylinder
+        } else if self.from.is_some() || self.to.is_some() {
+            CellGeometry::Lines
+        } else {
+            CellGeometry::Aligned
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CellDef {
+    pub solid: bool,
+    pub ceiling: bool,
+    pub road: bool,
+    pub floor_texture: u8,
+    pub ceiling_texture: u8,
+    pub walls: Vec<WallDef>,
+}
+
+// not flattened from `CellDef`, since serde loses the error positions of flattened fields
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PlacedCellDef {
+    pub x: usize,
+    pub y: usize,
+    #[serde(default)]
+    pub solid: bool,
+    #[serde(default)]
+    pub ceiling: bool,
+    #[serde(default)]
+    pub road: bool,
+    #[serde(default)]
+    pub floor_texture: u8,
+    #[serde(default)]
+    pub ceiling_texture: u8,
+    #[serde(default)]
+    pub walls: Vec<WallDef>,
+}
+
+impl PlacedCellDef {
+    pub fn cell(&self) -> CellDef {
+        CellDef {
+            solid: self.solid,
+            ceiling: self.ceiling,
+            road: self.road,
+            floor_texture: self.floor_texture,
+            ceiling_texture: self.ceiling_texture,
+            walls: self.walls.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SpriteDef {
+    pub x: f32,
+    pub y: f32,
+    #[serde(default)]
+    pub angle: i32,
+    #[serde(default = "default_sprite_height")]
+    pub height: f32, // multiplier of the wall height
+    pub r#type: i32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TextureBinding {
+    pub id: i32,
+    pub src: String,
+    pub width: i32,
+    pub height: i32,
+    #[serde(default = "default_texture_angles")]
+    pub angles: u32,
+}
+
+// the cells of a level file as they're written, to point errors about them at the right line
+#[derive(Deserialize)]
+struct CellSources<'a> {
+    #[serde(borrow, default)]
+    default: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    cells: Vec<&'a RawValue>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LevelFile {
+    pub width: usize,
+    pub height: usize,
+    #[serde(default)]
+    pub default: CellDef,
+    #[serde(default)]
+    pub cells: Vec<PlacedCellDef>,
+    #[serde(default)]
+    pub sprites: Vec<SpriteDef>,
+    #[serde(default)]
+    pub textures: Vec<TextureBinding>,
+}
+
+/// Line and column are 1-based; both are 0 when the error has no position in the file
+/// (e.g. a level that wasn't read from text, see `Level::from_file`).
+#[wasm_bindgen]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelError {
+    pub line: usize,
+    pub column: usize,
+    message: String,
+}
+
+#[wasm_bindgen]
+impl LevelError {
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+impl LevelError {
+    pub(crate) fn at(line: usize, column: usize, message: String) -> LevelError {
+        LevelError {
+            line,
+            column,
+            message,
+        }
+    }
+
+    pub(crate) fn unpositioned(message: String) -> LevelError {
+        LevelError::at(0, 0, message)
+    }
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}", self.message)
+        } else {
+            write!(
+                f,
+                "{} at line {} column {}",
+                self.message, self.line, self.column
+            )
+        }
+    }
+}
+
+impl From<serde_json::Error> for LevelError {
+    fn from(error: serde_json::Error) -> LevelError {
+        // serde_json appends the position to the message, keep it in the fields only
+        let message = error.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(idx) if error.line() > 0 => message[..idx].to_string(),
+            _ => message,
+        };
+
+        LevelError {
+            line: error.line(),
+            column: error.column(),
+            message,
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct Level {
+    pub width: usize,
+    pub height: usize,
+    pub(crate) grid: Vec<u64>,
+    pub(crate) materials: Vec<u64>,
+    pub(crate) sprite_data: Vec<f32>,
+    pub(crate) textures: Vec<TextureBinding>,
+}
+
+#[wasm_bindgen]
+impl Level {
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json(text: &str) -> Result<Level, LevelError> {
+        let file: LevelFile = serde_json::from_str(text)?;
+        Level::encode(file).map_err(|(source, error)| locate_cell(text, source, error))
+    }
+
+    /// The u64 cells, row by row, ready to be copied into the map array.
+    #[wasm_bindgen(getter)]
+    pub fn grid(&self) -> Vec<u64> {
+        self.grid.clone()
+    }
+
+    /// The parallel material layer, same size as the grid.
+    #[wasm_bindgen(getter)]
+    pub fn materials(&self) -> Vec<u64> {
+        self.materials.clone()
+    }
+
+    /// Sprites as flat (x, y, angle, height, type) floats, the format of `populateFromArray`.
+    #[wasm_bindgen(getter, js_name = spriteData)]
+    pub fn sprite_data(&self) -> Vec<f32> {
+        self.sprite_data.clone()
+    }
+
+    #[wasm_bindgen(js_name = spritesMap)]
+    pub fn sprites_map(&self) -> WasmStripePerCoordMap {
+        let mut sprites_map = WasmStripePerCoordMap::new();
+        sprites_map.populate_from_array(&self.sprite_data);
+        sprites_map
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn textures(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&self.textures).unwrap_or(JsValue::NULL)
+    }
+}
+
+impl Level {
+    pub fn from_file(file: LevelFile) -> Result<Level, LevelError> {
+        Level::encode(file).map_err(|(_, error)| error)
+    }
+
+    // like `from_file`, along with the part of the file an error is about
+    fn encode(file: LevelFile) -> Result<Level, (ErrorSource, LevelError)> {
+        let size = map_size(file.width, file.height).map_err(|error| (ErrorSource::Map, error))?;
+        let (default_value, default_material) = encode_cell(&file.default).map_err(|message| {
+            (
+                ErrorSource::Default,
+                LevelError::unpositioned(format!("default cell: {}", message)),
+            )
+        })?;
+        let mut grid = vec![default_value; size];
+        let mut materials = vec![default_material; size];
+
+        for (index, placed) in file.cells.iter().enumerate() {
+            if placed.x >= file.width || placed.y >= file.height {
+                return Err((
+                    ErrorSource::Cell(index),
+                    LevelError::unpositioned(format!(
+                        "cell ({}, {}) is outside of the {}x{} map",
+                        placed.x, placed.y, file.width, file.height
+                    )),
+                ));
+            }
+
+            let (value, material) = encode_cell(&placed.cell()).map_err(|message| {
+                (
+                    ErrorSource::Cell(index),
+                    LevelError::unpositioned(format!(
+                        "cell ({}, {}): {}",
+                        placed.x, placed.y, message
+                    )),
+                )
+            })?;
+            grid[placed.y * file.width + placed.x] = value;
+            materials[placed.y * file.width + placed.x] = material;
+        }
+
+        let sprite_data = file
+            .sprites
+            .iter()
+            .flat_map(|sprite| {
+                [
+                    sprite.x,
+                    sprite.y,
+                    sprite.angle as f32,
+                    sprite.height * 100.0,
+                    sprite.r#type as f32,
+                ]
+            })
+            .collect();
+
+        Ok(Level {
+            width: file.width,
+            height: file.height,
+            grid,
+            materials,
+            sprite_data,
+            textures: file.textures,
+        })
+    }
+
+    pub fn get_grid(&self) -> &[u64] {
+        &self.grid
+    }
+
+    pub fn get_materials(&self) -> &[u64] {
+        &self.materials
+    }
+
+    pub fn get_textures(&self) -> &[TextureBinding] {
+        &self.textures
+    }
+}
+
+/// The number of cells of a `width` by `height` map, or an error if it's larger than levels are
+/// allowed to be. Sizes read from files aren't trusted, check them before allocating anything.
+pub(crate) fn map_size(width: usize, height: usize) -> Result<usize, LevelError> {
+    width
+        .checked_mul(height)
+        .filter(|&size| size <= MAX_CELLS)
+        .ok_or_else(|| LevelError::unpositioned(format!("a {}x{} map is too large", width, height)))
+}
+
+// what in a level file an error found encoding it is about
+enum ErrorSource {
+    Map,
+    Default,
+    Cell(usize),
+}
+
+/// Points an error about a cell of the level in `text` at where the cell starts; other errors
+/// are left unpositioned.
+fn locate_cell(text: &str, source: ErrorSource, error: LevelError) -> LevelError {
+    let source = serde_json::from_str::<CellSources>(text)
+        .ok()
+        .and_then(|sources| match source {
+            ErrorSource::Map => None,
+            ErrorSource::Default => sources.default,
+            ErrorSource::Cell(index) => sources.cells.get(index).copied(),
+        });
+    let Some(source) = source else {
+        return error;
+    };
+
+    // the raw value borrows from the text, so where it starts is where its bytes are
+    let before = &text[..source.get().as_ptr() as usize - text.as_ptr() as usize];
+    let line_start = before.rfind('\n').map_or(0, |idx| idx + 1);
+    LevelError::at(
+        before.matches('\n').count() + 1,
+        before.len() - line_start + 1,
+        error.message,
+    )
+}
+
+/// Returns the cell bitfield along with its material layer value.
+fn encode_cell(def: &CellDef) -> Result<(u64, u64), String> {
+    if def.walls.len() > MAX_WALL_SEGMENTS {
+        return Err(format!(
+            "{} walls given, at most {} are supported",
+            def.walls.len(),
+            MAX_WALL_SEGMENTS
+        ));
+    }
+    if def.walls.get(2).is_some_and(|wall| wall.window) {
+        return Err("the third wall can't be a window".to_string());
+    }
+    // they share a door bit
+    if let [_, second, third] = def.walls.as_slice() {
+        if second.door != third.door {
+            return Err("the second and third walls must both be doors or neither".to_string());
+        }
+    }
+
+    let geometry = def
+        .walls
+        .first()
+        .map_or(CellGeometry::Aligned, WallDef::geometry);
+    if def.walls.iter().any(|wall| wall.geometry() != geometry) {
+        return Err(
+            "pillars, walls with from and to, and aligned walls can't share a cell".to_string(),
+        );
+    }
+
+    let mut cell = Cell::new();
+    let mut material = CellMaterial::new();
+    material.floor_texture = def.floor_texture;
+    material.ceiling_texture = def.ceiling_texture;
+    cell.has_wall = def.solid || !def.walls.is_empty();
+    cell.has_ceiling = def.ceiling;
+    cell.has_road = def.road;
+    cell.num_walls = def.walls.len() as u8;
+    cell.geometry = geometry;
+
+    for (index, wall) in def.walls.iter().enumerate() {
+        let segment = match (wall.centre, wall.from, wall.to) {
+            (Some(centre), _, _) => WallSegment::cylinder(centre[0], centre[1], wall.radius),
+            (None, Some(from), Some(to)) => {
+                WallSegment::line(from[0], from[1], to[0], to[1], wall.door, wall.window)
+            }
+            (None, None, None) => WallSegment::new(
+                wall.offset,
+                wall.thickness,
+                wall.depth,
+                wall.offset_secondary,
+                wall.north,
+                wall.door,
+                wall.window,
+            ),
+            _ => return Err("a wall at an angle needs both from and to".to_string()),
+        };
+        cell.set_segment(index, segment);
+        material.set_wall_texture(index, wall.texture);
+        material.set_wall_height(index, wall.height);
+        material.set_mirror(index, wall.mirror);
+    }
+
+    Ok((cell.to_u64(), material.to_u64()))
+}
+
+fn tenths<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let value = u8::deserialize(deserializer)?;
+    if value > 10 {
+        return Err(serde::de::Error::custom(format!(
+            "expected tenths of a cell (0-10), got {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+fn point<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[u8; 2]>, D::Error> {
+    let value = <[u8; 2]>::deserialize(deserializer)?;
+    if value.iter().any(|tenths| *tenths > 10) {
+        return Err(serde::de::Error::custom(format!(
+            "expected a point in tenths of a cell (0-10), got {:?}",
+            value
+        )));
+    }
+    Ok(Some(value))
+}
+
+fn wall_height<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let value = f32::deserialize(deserializer)?;
+    if !(0.25..=3.75).contains(&value) {
+        return Err(serde::de::Error::custom(format!(
+            "expected a wall height between 0.25 and 3.75, got {}",
+            value
+        )));
+    }
+    Ok(value)
+}
+
+fn default_wall_height() -> f32 {
+    1.0
+}
+
+fn default_sprite_height() -> f32 {
+    1.0
+}
+
+fn default_texture_angles() -> u32 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: &str = r#"{
+        "width": 3,
+        "height": 2,
+        "default": { "road": true },
+        "cells": [
+            { "x": 1, "y": 0, "ceiling": true, "walls": [{ "thickness": 1, "depth": 10, "north": true, "texture": 100 }] },
+            { "x": 2, "y": 1, "solid": true, "ceiling_texture": 3 },
+            { "x": 0, "y": 1, "walls": [{ "offset": 5, "thickness": 1, "depth": 10, "height": 0.5 }] }
+        ],
+        "sprites": [{ "x": 1.5, "y": 0.5, "angle": 90, "height": 0.5, "type": 7 }],
+        "textures": [{ "id": 7, "src": "barrel1.png", "width": 355, "height": 438 }]
+    }"#;
+
+    #[test]
+    fn loads_grid_and_sprites() {
+        let level = Level::from_json(LEVEL).unwrap();
+
+        assert_eq!((3, 2), (level.width, level.height));
+        assert_eq!(
+            &[0x8, 0x000000000A101043, 0x8, 0x000000000A151001, 0x8, 0x1],
+            level.get_grid()
+        );
+        assert_eq!(
+            &[0, 100, 0, 0x0200_0000_0000, 0, 0x0300000000],
+            level.get_materials()
+        );
+        assert_eq!(vec![1.5, 0.5, 90.0, 50.0, 7.0], level.sprite_data());
+        assert_eq!(1, level.get_textures()[0].angles);
+        assert_eq!(1, level.sprites_map().count_cells());
+    }
+
+    #[test]
+    fn reports_syntax_error_position() {
+        let error = Level::from_json("{\n  \"width\": 3,\n  \"height\" 2\n}")
+            .err()
+            .unwrap();
+
+        assert_eq!((3, 12), (error.line, error.column));
+    }
+
+    #[test]
+    fn reports_out_of_range_nibble_position() {
+        let text = "{ \"width\": 1, \"height\": 1,\n\"cells\": [{ \"x\": 0, \"y\": 0, \"walls\": [{ \"depth\": 12 }] }] }";
+        let error = Level::from_json(text).err().unwrap();
+
+        assert_eq!(2, error.line);
+        assert!(error.message().contains("0-10"));
+    }
+
+    #[test]
+    fn encodes_walls_at_an_angle() {
+        let text = r#"{ "width": 1, "height": 1, "cells": [{ "x": 0, "y": 0, "walls": [{ "from": [0, 2], "to": [10, 8], "window": true }] }] }"#;
+        assert_eq!(&[0x8A201501], Level::from_json(text).unwrap().get_grid());
+
+        let mixed = r#"{ "width": 1, "height": 1, "cells": [{ "x": 0, "y": 0, "walls": [{ "from": [0, 2], "to": [10, 8] }, { "depth": 10 }] }] }"#;
+        assert!(Level::from_json(mixed)
+            .err()
+            .unwrap()
+            .message()
+            .contains("aligned"));
+    }
+
+    #[test]
+    fn rejects_a_door_on_only_one_of_the_walls_sharing_its_bit() {
+        let text = r#"{ "width": 1, "height": 1, "cells": [{ "x": 0, "y": 0, "walls": [{}, {}, { "door": true }] }] }"#;
+        let error = Level::from_json(text).err().unwrap();
+        assert!(error.message().contains("both be doors"));
+
+        let both = text.replace("{}, {}", r#"{}, { "door": true }"#);
+        assert!(Level::from_json(&both).is_ok());
+    }
+
+    #[test]
+    fn encodes_pillars() {
+        let text = r#"{ "width": 1, "height": 1, "cells": [{ "x": 0, "y": 0, "walls": [{ "centre": [5, 5], "radius": 3 }] }] }"#;
+        assert_eq!(&[0x03551801], Level::from_json(text).unwrap().get_grid());
+    }
+
+    #[test]
+    fn rejects_cells_outside_of_map() {
+        let text = r#"{ "width": 1, "height": 1, "cells": [{ "x": 1, "y": 0, "solid": true }] }"#;
+        let error = Level::from_json(text).err().unwrap();
+
+        assert_eq!((1, 38), (error.line, error.column));
+        assert!(error.message().contains("(1, 0)"));
+    }
+
+    #[test]
+    fn rejects_maps_too_large_to_hold() {
+        for (width, height) in [(1 << 13, 1 << 13), (usize::MAX, 2)] {
+            let text = format!(r#"{{ "width": {}, "height": {} }}"#, width, height);
+            let error = Level::from_json(&text).err().unwrap();
+
+            assert!(error.message().contains("too large"));
+            assert_eq!(0, error.line);
+        }
+    }
+
+    #[test]
+    fn reports_position_of_malformed_cells() {
+        let text = r#"{
+            "width": 2, "height": 1,
+            "default": { "walls": [{ "window": true }, {}, { "window": true }] },
+            "cells": [
+                { "x": 0, "y": 0, "solid": true },
+                { "x": 1, "y": 0, "walls": [{}, {}, {}, {}] }
+            ]
+        }"#;
+        let error = Level::from_json(text).err().unwrap();
+        assert_eq!((3, 24), (error.line, error.column));
+        assert!(error.message().contains("third wall"));
+
+        let text = text.replace(r#"{}, { "window": true }"#, "{}");
+        let error = Level::from_json(&text).err().unwrap();
+        assert_eq!((6, 17), (error.line, error.column));
+        assert!(error.message().contains("4 walls"));
+
+        let file: LevelFile = serde_json::from_str(&text).unwrap();
+        assert_eq!(0, Level::from_file(file).err().unwrap().line);
+    }
+}
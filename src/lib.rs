@@ -1,9 +1,10 @@
 #![feature(map_try_insert)]
 #![feature(portable_simd)]
+use cell::{Cell, CellGeometry, CellMaterial};
 use helpers::{
-    fixed_mul, get_bits, get_grid_value, has_bit_set, to_fixed, to_fixed_large,
-    BackgroundImageWasm, Position, Sprite, SpritePart, Texture, TextureType, TranslationResult,
-    WasmStripePerCoordMap, WasmTextureMap, WasmTextureMetaMap, FIXED_ONE, FIXED_SHIFT,
+    distance_opacity, fixed_mul, to_fixed, to_fixed_large, BackgroundImageWasm, Position, Sprite,
+    SpritePart, Texture, TextureType, TranslationResult, WasmStripePerCoordMap, WasmTextureMap,
+    WasmTextureMetaMap, FIXED_ONE, FIXED_SHIFT,
 };
 use js_sys::Float32Array;
 use js_sys::Math::atan2;
@@ -15,65 +16,69 @@ use rayon::prelude::*;
 #[cfg(feature = "parallel")]
 pub use wasm_bindgen_rayon::init_thread_pool;
 
+pub mod ascii;
+pub mod binary;
+pub mod camera;
+pub mod cell;
+pub mod doors;
+pub mod grid;
 mod helpers;
+pub mod hit;
+pub mod layers;
+pub mod level;
 mod line_intersection;
+pub mod pick;
+pub mod portals;
+pub mod push_walls;
+pub mod tiled;
+pub mod validation;
+pub mod view;
+use camera::{Camera, STANDING_EYE_HEIGHT};
+use doors::{DoorState, DoorStates};
 use geo::{Coord, Distance, Euclidean, Line};
+use grid::MapGrid;
+use hit::{HitKind, RayHit};
+use layers::WasmMapLayers;
 use line_intersection::LineInterval;
+use pick::{SpriteHit, WasmPickBuffer};
+use portals::Portals;
+use push_walls::PushWalls;
 use std::collections::HashSet;
 use std::f32::consts::PI;
 use std::{collections::HashMap, f32::MAX};
+use view::ViewTransform;
 use web_sys::console;
 // let js: JsValue = vec![found_sprites_length as f32].into();
 // console::log_2(&"Znj?".into(), &js);
-use std::slice::from_raw_parts;
 use std::slice::from_raw_parts_mut;
 
 #[wasm_bindgen]
 #[inline(never)]
 pub fn render(
-    x: f32,
-    y: f32,
-    dir_x: f32,
-    dir_y: f32,
-    plane_x: f32,
-    plane_y: f32,
-    pitch: i32,
-    z: i32,
-    plane_y_initial: f32,
+    camera: &Camera,
     render_img: *mut u8,
     zbuffer_array: *mut f32,
-    map_array: *mut u64, // 2D array representing the grid map
-    map_width: usize,    // Needed to index into 1D map
+    layers: &WasmMapLayers, // grids of all storeys, sharing one size
     width: i32,
     height: i32,
     light_range: i32,
-    range: i8,
+    max_distance: f32, // view distance in cells, things fade out into the sky before it
     map_light: i32,
+    mirror_bounces: u32, // how often rays reflect off mirrors or go through portals, 0 draws them as plain walls
+    mirror_tint: u32,    // RGBA, blended over what's seen in mirrors by alpha per bounce
     background: &BackgroundImageWasm,
     sprites_map: &WasmStripePerCoordMap, // sprites per x y coordinate
     sprites_texture_map: &WasmTextureMap, // contains textures along with angled textures
     sprites_texture_meta_map: &WasmTextureMetaMap,
+    pick_buffer: &mut WasmPickBuffer, // remembers what was drawn where, for `pick`
 ) {
-    let position = Position {
-        x,
-        y,
-        dir_x,
-        dir_y,
-        plane_x,
-        plane_y,
-        pitch,
-        z,
-        plane_y_initial,
-        map_x: x.floor() as i32,
-        map_y: y.floor() as i32,
-    };
+    let position = camera.position(width, height);
 
     let img_slice =
         unsafe { std::slice::from_raw_parts_mut(render_img, width as usize * height as usize * 4) };
 
-    let map_data = unsafe { from_raw_parts(map_array, (map_width * map_width) as usize) };
-
     let zbuffer = unsafe { from_raw_parts_mut(zbuffer_array, width as usize) };
+    let mut mirror_depths = vec![0.0; width as usize];
 
     let wall_texture_meta = sprites_texture_meta_map
         .get_map()
@@ -84,32 +89,8 @@ pub fn render(
         .get(&(TextureType::WALL as i32, 0))
         .unwrap();
 
-    let ceiling_texture_meta = sprites_texture_meta_map
-        .get_map()
-        .get(&(TextureType::CEILING as i32))
-        .unwrap();
-    let ceiling_texture = sprites_texture_map
-        .get_map()
-        .get(&(TextureType::CEILING as i32, 0))
-        .unwrap();
-
-    let floor_texture_meta = sprites_texture_meta_map
-        .get_map()
-        .get(&(TextureType::FLOOR as i32))
-        .unwrap();
-    let floor_texture = sprites_texture_map
-        .get_map()
-        .get(&(TextureType::FLOOR as i32, 0))
-        .unwrap();
-
-    let road_texture_meta = sprites_texture_meta_map
-        .get_map()
-        .get(&(TextureType::ROAD as i32))
-        .unwrap();
-    let road_texture = sprites_texture_map
-        .get_map()
-        .get(&(TextureType::ROAD as i32, 0))
-        .unwrap();
+    // floor & ceiling textures are looked up per pixel, so skip the hashing
+    let ground_textures = sprites_texture_map.texture_lookup(sprites_texture_meta_map);
 
     let door_texture_meta = sprites_texture_meta_map
         .get_map()
@@ -120,67 +101,107 @@ pub fn render(
         .get(&(TextureType::DOOR as i32, 0))
         .unwrap();
 
-    let mut found_sprites: SmallVec<[Sprite; 1024]> = vec![].into();
-
     draw_background_image_prescaled(&position, background, img_slice, width, height);
-    draw_ceiling_floor_raycast(
-        &position,
-        img_slice,
-        floor_texture,
-        ceiling_texture,
-        road_texture,
-        width,
-        height,
-        light_range,
-        map_light,
-        floor_texture_meta.width,
-        floor_texture_meta.height,
-        ceiling_texture_meta.width,
-        ceiling_texture_meta.height,
-        road_texture_meta.width,
-        road_texture_meta.height,
-        map_data,
-        map_width,
-    );
-    draw_walls_raycast(
-        &position,
-        zbuffer,
-        map_data,
-        map_width,
-        width,
-        range,
-        wall_texture_meta.width,
-        sprites_map,
-        &mut found_sprites,
-    );
-    draw_sprites_wasm(
-        &position,
-        img_slice,
-        width,
-        height,
-        zbuffer,
-        light_range,
-        map_light,
-        sprites_texture_map,
-        sprites_texture_meta_map,
-        &mut found_sprites,
-    );
+    pick_buffer.begin_frame(&position, width, height, max_distance);
+
+    // sprites stand on the bottom layer
+    let no_sprites = WasmStripePerCoordMap::new();
+    let current_layer = layers.layer_at(position.z - STANDING_EYE_HEIGHT);
+
+    for layer in layers.draw_order(current_layer) {
+        // the other layers are drawn as if we were standing that much higher or lower
+        let layer_position = Position {
+            z: position.z - layers.floor_height(layer),
+            ..position
+        };
+        let cells = layers.cells(layer);
+        let materials = layers.materials(layer);
+
+        let mut found_sprites: SmallVec<[Sprite; 1024]> = vec![].into();
+
+        // the floors of lower layers are seen from above, the ceilings of upper ones from below
+        draw_ceiling_floor_raycast(
+            &layer_position,
+            img_slice,
+            &ground_textures,
+            width,
+            height,
+            light_range,
+            map_light,
+            max_distance,
+            cells,
+            materials,
+            layer <= current_layer,
+            layer >= current_layer,
+        );
+        draw_walls_raycast(
+            &layer_position,
+            zbuffer,
+            &mut mirror_depths,
+            cells,
+            materials,
+            layers.doors(layer),
+            layers.push_walls(layer),
+            layers.portals(layer),
+            layers.tallest_wall(layer),
+            width,
+            max_distance,
+            wall_texture_meta.width,
+            mirror_bounces,
+            if layer == 0 { sprites_map } else { &no_sprites },
+            &mut found_sprites,
+        );
+        draw_sprites_wasm(
+            &layer_position,
+            img_slice,
+            width,
+            height,
+            zbuffer,
+            &mirror_depths,
+            light_range,
+            map_light,
+            max_distance,
+            mirror_tint,
+            sprites_texture_map,
+            sprites_texture_meta_map,
+            &mut found_sprites,
+            pick_buffer,
+        );
+    }
+}
+
+/// What a ray cast by `raycast_column` found.
+pub struct ColumnCast {
+    /// Distance along the view direction to what the ray stopped at, in the units of the zbuffer.
+    pub perp_wall_dist: f32,
+    /// What the ray stopped at, None if it reached `max_distance` first.
+    pub ray_hit: Option<RayHit>,
+    /// The cells with sprites the ray passed, with how they're seen in mirrors or through portals.
+    pub met_coords: Vec<(i32, i32, ViewTransform)>,
+    /// Up to `max_surfaces` walls, doors and windows the ray passed, nearest first; the hit is
+    /// among them.
+    pub surfaces: SmallVec<[RayHit; 2]>,
 }
 
 pub fn raycast_column(
     column: i32,
     position: &Position,
-    map_data: &[u64],
-    map_width: usize, // Needed to index into 1D map
+    cells: MapGrid,
+    materials: MapGrid, // may be empty, in which case default textures are used
+    doors: &DoorStates, // how far the doors of the grid are open
+    push_walls: &PushWalls, // walls that are being pushed, see `push_walls`
+    portals: &Portals,  // segments linked to other ones, see `portals`
     width: i32,
-    range: i8,
+    max_distance: f32, // in cells, along the view direction
     wall_texture_width: i32,
-    sprites_map: Option<&HashMap<(i32, i32), Vec<Sprite>>>,
-    skip_sprites_and_writes: bool,
-    stop_at_window: bool,
-) -> (f32, [i32; 7], Vec<(i32, i32)>, SmallVec<[Sprite; 2]>) {
-    let mut met_coords: Vec<(i32, i32)> = Vec::new();
-    let mut window_sprites: SmallVec<[Sprite; 2]> = SmallVec::with_capacity(2);
+    sprites_map: Option<&HashMap<(i32, i32), Vec<Sprite>>>, // to collect the cells with sprites
+    max_surfaces: usize, // how many of the surfaces along the ray to return, nearest first
+    stop_at_first_hit: bool, // otherwise rays continue through windows and low walls
+    tallest_wall: f32, // walls at least this tall stop the ray, lower ones only while taller ones may show above
+    max_bounces: u32, // how often the ray reflects off mirrors or goes through portals, 0 stops it at them
+) -> ColumnCast {
+    let mut met_coords: Vec<(i32, i32, ViewTransform)> = Vec::new();
+    let mut surfaces: SmallVec<[RayHit; 2]> = SmallVec::new();
 
     let default_sprites_map = HashMap::new();
     let sprites_map = sprites_map.unwrap_or_else(|| &default_sprites_map);
@@ -227,24 +248,22 @@ pub fn raycast_column(
     }
 
     let mut hit = false;
-    let mut hit_type: i8 = 1;
-    let mut remaining_range: i8 = range;
-    let initial_bit_offset = 16;
+    // the segment the ray stopped at, its perpendicular distance is filled in at the end
+    let mut segment_hit: Option<RayHit> = None;
+    // how far along the ray the current cell starts, in ray direction lengths
+    let mut cell_ray_length: f32 = 0.0;
+    let max_ray_length = max_distance / position.dir_x.hypot(position.dir_y);
 
     let position_coord = Coord::from([position.x, position.y]);
 
     // local pre-initialized while/for loop variables
-    let mut bit_width = 0;
     let mut local_width: f32 = 1.0;
     let mut local_offset: f32 = 1.0;
-    let mut bit_offset = 0;
-    let mut bit_thickness = 0;
-    let mut bit_offset_secondary = 0;
-    let mut is_door = false;
-    let mut is_window = false;
-    let mut is_east = false;
     let mut local_distance_multiplier = 0.0;
+    // set when the ray stops at a wall at an angle or a pillar, whose distance doesn't follow from the sides
+    let mut exact_ray_length: Option<f32> = None;
     let mut local_side = 0;
+    let mut local_normal: (f32, f32) = (0.0, 0.0);
     let mut local_intersection_coord: Coord<f32> = Coord::zero();
     // from east or west side
     // offset is defined from the east or north
@@ -258,223 +277,416 @@ pub fn raycast_column(
     let mut new_map_end_y;
     let mut segment_map_adder;
 
+    // the highest top of the walls passed, as its rise over the distance from the eyes; a wall
+    // farther away whose top doesn't rise more is hidden behind them
+    let mut horizon = f32::NEG_INFINITY;
+    // whether a wall as tall as the tallest one past `ray_length` would show above `horizon`; above
+    // the eyes walls farther away show lower, below them higher
+    let may_show_past = |horizon: f32, ray_length: f32| {
+        let rise = tallest_wall - position.z;
+        rise / if rise >= 0.0 {
+            ray_length
+        } else {
+            max_ray_length
+        } > horizon
+    };
+
+    // a solid cell, entered through `side` `ray_length` along the ray
+    let solid_hit = |map_x: i32, map_y: i32, side: i32, ray_length: f32| {
+        let x = position.x + ray_dir_x * ray_length;
+        let y = position.y + ray_dir_y * ray_length;
+        let material = CellMaterial::from_u64(materials.get(map_x, map_y));
+        let texture = match material.wall_texture(0) {
+            0 => TextureType::WALL as i32,
+            texture => texture as i32,
+        };
+        let normal = if side == 0 {
+            (-step_x as f32, 0.0)
+        } else {
+            (0.0, -step_y as f32)
+        };
+
+        RayHit {
+            x,
+            y,
+            perp_distance: ray_length,
+            distance: Euclidean.distance(Coord::from([x, y]), position_coord),
+            map_x,
+            map_y,
+            segment: None,
+            side: side as u8,
+            normal_x: normal.0,
+            normal_y: normal.1,
+            texture,
+            u: if side == 0 { y.fract() } else { x.fract() },
+            height: material.wall_height(0),
+            lift: 0.0,
+            kind: if material.is_mirror(0) {
+                HitKind::Mirror
+            } else {
+                HitKind::Wall
+            },
+            bounces: 0,
+            portals: 0,
+        }
+    };
+
     // ray between player position and point on the ray direction
     let line = LineInterval::ray(Line {
         start: (position.x, position.y).into(),
         end: (position.x + ray_dir_x, position.y + ray_dir_y).into(),
     });
 
-    while !hit && remaining_range >= 0 {
-        let value: u64 = get_grid_value(map_x, map_y, map_width as i32, map_data);
-        let num_walls = get_bits(value, 12); // since the upper two are reserves we can afford this
+    while !hit && cell_ray_length <= max_ray_length {
+        let cell = Cell::from_u64(cells.get(map_x, map_y));
 
         // if wall bit is set
-        if num_walls > 0 {
-            hit_type = 1 as i8;
+        if !cell.walls().is_empty() {
+            let material = CellMaterial::from_u64(materials.get(map_x, map_y));
+            let door = doors.get(map_x, map_y);
 
             let mut distance_multiplier = 0.0; // how much to move back/forward the distance due to internal offsets
             let mut distance = MAX;
 
             // we support up to three lines per coordinate
-            for i in 0..num_walls {
-                match i {
-                    0 => {
-                        bit_width = get_bits(value, initial_bit_offset + 8);
-                        bit_offset = get_bits(value, initial_bit_offset);
-                        bit_thickness = get_bits(value, initial_bit_offset + 4);
-                        bit_offset_secondary = get_bits(value, initial_bit_offset + 12);
-                        is_door = has_bit_set(value, 5);
-                        is_east = !has_bit_set(value, 6);
-                        is_window = has_bit_set(value, 8);
-                    }
-                    1 => {
-                        bit_width = get_bits(value, initial_bit_offset + 24);
-                        bit_offset = get_bits(value, initial_bit_offset + 16);
-                        bit_thickness = get_bits(value, initial_bit_offset + 20);
-                        bit_offset_secondary = get_bits(value, initial_bit_offset + 28);
-                        is_door = has_bit_set(value, 4);
-                        is_east = !has_bit_set(value, 7);
-                        is_window = has_bit_set(value, 9);
+            for (segment_index, segment) in cell.walls().iter().enumerate() {
+                let is_door = segment.is_door;
+                let is_east = !segment.is_north;
+                let is_window = segment.is_window;
+                let wall_height = material.wall_height(segment_index);
+                let lift = door.filter(|_| is_door).map_or(0.0, DoorState::lift);
+                // windows, walls lower than a full wall and raised doors can be seen over / through / under
+                let is_see_through = is_window || wall_height < 1.0 || lift > 0.0;
+                // walls lower than the tallest one too, while it may show above them; mirrors and
+                // portals send the ray on instead
+                let looks_past = wall_height < tallest_wall
+                    && !material.is_mirror(segment_index)
+                    && portals.get(map_x, map_y, segment_index as u8).is_none();
+
+                let mut local_hit = false;
+                // texture fract and ray length of walls at an angle and pillars, which have no sides
+                let mut local_exact: Option<(f32, f32)> = None;
+
+                if cell.geometry == CellGeometry::Lines {
+                    let ((start_x, start_y), (end_x, end_y)) = segment.endpoints();
+                    let start: Coord<f32> = (map_x as f32 + start_x, map_y as f32 + start_y).into();
+                    let end: Coord<f32> = (map_x as f32 + end_x, map_y as f32 + end_y).into();
+                    let length = Euclidean.distance(start, end);
+
+                    let wall = LineInterval::line_segment(Line { start, end });
+                    if let Some(coord) = wall.relate(&line).unique_intersection() {
+                        if length > 0.0 {
+                            local_intersection_coord = coord;
+                            local_hit = true;
+                            local_width = 1.0;
+                            local_offset = 0.0;
+
+                            let ray_length = ((coord.x - position.x) * ray_dir_x
+                                + (coord.y - position.y) * ray_dir_y)
+                                / (ray_dir_x * ray_dir_x + ray_dir_y * ray_dir_y);
+                            local_exact =
+                                Some((Euclidean.distance(start, coord) / length, ray_length));
+                            local_normal = ((start.y - end.y) / length, (end.x - start.x) / length);
+                        }
                     }
-                    2 => {
-                        bit_width = get_bits(value, initial_bit_offset + 40);
-                        bit_offset = get_bits(value, initial_bit_offset + 32);
-                        bit_thickness = get_bits(value, initial_bit_offset + 36);
-                        bit_offset_secondary = get_bits(value, initial_bit_offset + 44);
-                        is_door = has_bit_set(value, 4);
-                        is_east = !has_bit_set(value, 2);
-                        is_window = false;
+                } else if cell.geometry == CellGeometry::Cylinder {
+                    let ((centre_x, centre_y), radius) = segment.circle();
+                    let centre_x = map_x as f32 + centre_x;
+                    let centre_y = map_y as f32 + centre_y;
+                    let from_centre_x = position.x - centre_x;
+                    let from_centre_y = position.y - centre_y;
+
+                    // solve |position + ray_length * ray_dir - centre| = radius
+                    let a = ray_dir_x * ray_dir_x + ray_dir_y * ray_dir_y;
+                    let b = 2.0 * (from_centre_x * ray_dir_x + from_centre_y * ray_dir_y);
+                    let c = from_centre_x * from_centre_x + from_centre_y * from_centre_y
+                        - radius * radius;
+                    let discriminant = b * b - 4.0 * a * c;
+
+                    // from inside of a pillar (c < 0) there's nothing to see
+                    if radius > 0.0 && c > 0.0 && discriminant >= 0.0 {
+                        let ray_length = (-b - discriminant.sqrt()) / (2.0 * a);
+                        if ray_length > 0.0 {
+                            let hit_x = position.x + ray_dir_x * ray_length;
+                            let hit_y = position.y + ray_dir_y * ray_length;
+                            local_intersection_coord = (hit_x, hit_y).into();
+                            local_hit = true;
+                            local_width = 1.0;
+                            local_offset = 0.0;
+
+                            // the texture wraps around once per cell of circumference
+                            let angle = (hit_y - centre_y).atan2(hit_x - centre_x) + PI;
+                            local_exact = Some(((angle * radius).fract(), ray_length));
+                            local_normal =
+                                ((hit_x - centre_x) / radius, (hit_y - centre_y) / radius);
+                        }
                     }
-                    _ => (),
-                };
-
-                let offset1: f32 = (bit_offset % 11) as f32 / 10.0;
-                let thickness: f32 = (bit_thickness % 11) as f32 / 10.0;
-                let offset_secondary: f32 = (bit_offset_secondary % 11) as f32 / 10.0;
-                let depth: f32 = (bit_width % 11) as f32 / 10.0;
-
-                if is_east {
-                    ray_dirs = [ray_dir_x, ray_dir_y];
-                    sides = [0, 1];
-                } else {
-                    ray_dirs = [ray_dir_y, ray_dir_x];
-                    sides = [1, 0];
-                }
+                } else if let Some(door) = door.filter(|door| is_door && door.open > 0.0) {
+                    // an opening door is a thin panel in the middle of the doorway
+                    let thickness = (segment.thickness % 11) as f32 / 10.0;
+                    let Some(panel) = door.panel(
+                        (segment.offset_secondary % 11) as f32 / 10.0,
+                        (segment.depth % 11) as f32 / 10.0,
+                        (segment.offset % 11) as f32 / 10.0 + thickness / 2.0,
+                    ) else {
+                        // slid all the way out of the doorway
+                        continue;
+                    };
+                    let to_map = |(along, across): (f32, f32)| -> Coord<f32> {
+                        if is_east {
+                            (map_x as f32 + across, map_y as f32 + along).into()
+                        } else {
+                            (map_x as f32 + along, map_y as f32 + across).into()
+                        }
+                    };
+                    let start = to_map(panel.start);
+                    let end = to_map(panel.end);
+                    let length = Euclidean.distance(start, end);
 
-                if ray_dirs[0] <= 0.0 {
-                    offset = offset1 + thickness;
-                    // from east side
-                    distance_offset = offset;
-                } else {
-                    offset = offset1;
-                    distance_offset = 1.0 - offset;
-                }
+                    let wall = LineInterval::line_segment(Line { start, end });
+                    if let Some(coord) = wall.relate(&line).unique_intersection() {
+                        local_intersection_coord = coord;
+                        local_hit = true;
+                        local_width = 1.0;
+                        local_offset = 0.0;
 
-                // find the intersection of a line segment and an infinite line
-                if is_east {
-                    new_map_start_x = map_x as f32 + offset;
-                    new_map_end_x = new_map_start_x;
-                    new_map_start_y = map_y as f32 + offset_secondary;
-                    new_map_end_y = new_map_start_y + depth;
+                        let ray_length = ((coord.x - position.x) * ray_dir_x
+                            + (coord.y - position.y) * ray_dir_y)
+                            / (ray_dir_x * ray_dir_x + ray_dir_y * ray_dir_y);
+                        let along = Euclidean.distance(start, coord) / length;
+                        local_exact = Some((
+                            panel.u_start + (panel.u_end - panel.u_start) * along,
+                            ray_length,
+                        ));
+                        local_normal = ((start.y - end.y) / length, (end.x - start.x) / length);
+                    }
                 } else {
-                    new_map_start_y = map_y as f32 + offset;
-                    new_map_end_y = new_map_start_y;
-                    new_map_start_x = map_x as f32 + offset_secondary;
-                    new_map_end_x = new_map_start_x + depth;
-                }
+                    let offset1: f32 = (segment.offset % 11) as f32 / 10.0;
+                    let thickness: f32 = (segment.thickness % 11) as f32 / 10.0;
+                    let offset_secondary: f32 = (segment.offset_secondary % 11) as f32 / 10.0;
+                    let depth: f32 = (segment.depth % 11) as f32 / 10.0;
 
-                // the segment of line at the offset of the wall
-                let segment = LineInterval::line_segment(Line {
-                    start: (new_map_start_x, new_map_start_y).into(),
-                    end: (new_map_end_x, new_map_end_y).into(),
-                });
-
-                // check main segment line
-                let intersection = segment.relate(&line).unique_intersection();
-
-                let mut local_hit = false;
-                if let Some(coord) = intersection {
-                    local_intersection_coord = coord;
-                    local_hit = true;
-
-                    // move it back for the amount it should move back (assign to both even though only 1 will be used, x for east/west and y for north/south)
-                    local_distance_multiplier = 1.0 - (distance_offset);
-
-                    local_side = sides[0];
-
-                    // since we'd like texture to match the width if it's a door
-                    if is_door || is_window {
-                        local_width = depth;
-                        local_offset = offset_secondary;
+                    if is_east {
+                        ray_dirs = [ray_dir_x, ray_dir_y];
+                        sides = [0, 1];
                     } else {
-                        local_width = 1.0;
-                        local_offset = 0.0;
+                        ray_dirs = [ray_dir_y, ray_dir_x];
+                        sides = [1, 0];
                     }
-                } else {
-                    // the segment of line between the offsets of the wall
-                    if ray_dirs[1] > 0.0 {
-                        // depending on which side we're looking at the space between the offsets from
-                        segment_map_adder = offset_secondary;
+
+                    if ray_dirs[0] <= 0.0 {
+                        offset = offset1 + thickness;
+                        // from east side
+                        distance_offset = offset;
                     } else {
-                        segment_map_adder = offset_secondary + depth;
+                        offset = offset1;
+                        distance_offset = 1.0 - offset;
                     }
 
+                    // find the intersection of a line segment and an infinite line
                     if is_east {
-                        new_map_start_x = map_x as f32 + offset1;
-                        new_map_end_x = new_map_start_x + thickness;
-                        new_map_start_y = map_y as f32 + segment_map_adder;
-                        new_map_end_y = new_map_start_y;
-                    } else {
-                        new_map_start_x = map_x as f32 + segment_map_adder;
+                        new_map_start_x = map_x as f32 + offset;
                         new_map_end_x = new_map_start_x;
-                        new_map_start_y = map_y as f32 + offset1;
-                        new_map_end_y = new_map_start_y + thickness;
+                        new_map_start_y = map_y as f32 + offset_secondary;
+                        new_map_end_y = new_map_start_y + depth;
+                    } else {
+                        new_map_start_y = map_y as f32 + offset;
+                        new_map_end_y = new_map_start_y;
+                        new_map_start_x = map_x as f32 + offset_secondary;
+                        new_map_end_x = new_map_start_x + depth;
                     }
 
-                    // the segment of line between the offsets of the wall
-                    let segment_between = LineInterval::line_segment(Line {
+                    // the segment of line at the offset of the wall
+                    let segment = LineInterval::line_segment(Line {
                         start: (new_map_start_x, new_map_start_y).into(),
                         end: (new_map_end_x, new_map_end_y).into(),
                     });
 
-                    // check line between segments of thickness
-                    let intersection_between = segment_between.relate(&line).unique_intersection();
-                    if let Some(coord) = intersection_between {
+                    // check main segment line
+                    let intersection = segment.relate(&line).unique_intersection();
+
+                    if let Some(coord) = intersection {
                         local_intersection_coord = coord;
                         local_hit = true;
-                        local_side = sides[1];
-                        hit_type = 1; // show wall even if door since this is the side
 
-                        // no texture x snehaningans from the sides
-                        local_width = 1.0;
-                        local_offset = 0.0;
+                        // move it back for the amount it should move back (assign to both even though only 1 will be used, x for east/west and y for north/south)
+                        local_distance_multiplier = 1.0 - (distance_offset);
 
-                        if ray_dirs[1] < 0.0 {
-                            // move it back for the amount it should move back due to depth
-                            // && move it forward for the amount it should move forward due to secondary offset
-                            // if we're looking at it from the shortened side
-                            local_distance_multiplier = 1.0 - depth - offset_secondary;
+                        local_side = sides[0];
+                        local_normal = if local_side == 0 {
+                            (1.0, 0.0)
                         } else {
-                            // move it back for the amount it should move back due to secondary offset
-                            local_distance_multiplier = offset_secondary;
+                            (0.0, 1.0)
+                        };
+
+                        // since we'd like texture to match the width if it's a door
+                        if is_door || is_window {
+                            local_width = depth;
+                            local_offset = offset_secondary;
+                        } else {
+                            local_width = 1.0;
+                            local_offset = 0.0;
+                        }
+                    } else {
+                        // the segment of line between the offsets of the wall
+                        if ray_dirs[1] > 0.0 {
+                            // depending on which side we're looking at the space between the offsets from
+                            segment_map_adder = offset_secondary;
+                        } else {
+                            segment_map_adder = offset_secondary + depth;
+                        }
+
+                        if is_east {
+                            new_map_start_x = map_x as f32 + offset1;
+                            new_map_end_x = new_map_start_x + thickness;
+                            new_map_start_y = map_y as f32 + segment_map_adder;
+                            new_map_end_y = new_map_start_y;
+                        } else {
+                            new_map_start_x = map_x as f32 + segment_map_adder;
+                            new_map_end_x = new_map_start_x;
+                            new_map_start_y = map_y as f32 + offset1;
+                            new_map_end_y = new_map_start_y + thickness;
+                        }
+
+                        // the segment of line between the offsets of the wall
+                        let segment_between = LineInterval::line_segment(Line {
+                            start: (new_map_start_x, new_map_start_y).into(),
+                            end: (new_map_end_x, new_map_end_y).into(),
+                        });
+
+                        // check line between segments of thickness
+                        let intersection_between =
+                            segment_between.relate(&line).unique_intersection();
+                        if let Some(coord) = intersection_between {
+                            local_intersection_coord = coord;
+                            local_hit = true;
+                            local_side = sides[1];
+                            local_normal = if local_side == 0 {
+                                (1.0, 0.0)
+                            } else {
+                                (0.0, 1.0)
+                            };
+
+                            // no texture x snehaningans from the sides
+                            local_width = 1.0;
+                            local_offset = 0.0;
+
+                            if ray_dirs[1] < 0.0 {
+                                // move it back for the amount it should move back due to depth
+                                // && move it forward for the amount it should move forward due to secondary offset
+                                // if we're looking at it from the shortened side
+                                local_distance_multiplier = 1.0 - depth - offset_secondary;
+                            } else {
+                                // move it back for the amount it should move back due to secondary offset
+                                local_distance_multiplier = offset_secondary;
+                            }
                         }
                     }
                 }
                 if local_hit {
+                    // face the camera
+                    if local_normal.0 * ray_dir_x + local_normal.1 * ray_dir_y > 0.0 {
+                        local_normal = (-local_normal.0, -local_normal.1);
+                    }
+                    if local_exact.is_some() {
+                        local_side = (local_normal.1.abs() > local_normal.0.abs()) as i32;
+                    }
+
                     // take the shortest of the two paths
                     let local_distance =
                         Euclidean.distance(local_intersection_coord, position_coord);
                     if local_distance < distance {
-                        // we'll only use this data if we're stopping at a window or it's not a window
-                        if !is_window || stop_at_window {
+                        // how far along the ray, which is the perpendicular distance
+                        let ray_length = ((local_intersection_coord.x - position.x) * ray_dir_x
+                            + (local_intersection_coord.y - position.y) * ray_dir_y)
+                            / (ray_dir_x * ray_dir_x + ray_dir_y * ray_dir_y);
+                        let top = (wall_height - position.z) / ray_length;
+                        // we'll only use this data if we're stopping at anything or it can't be seen past
+                        let stops = stop_at_first_hit
+                            || !(is_see_through
+                                || (looks_past && may_show_past(horizon.max(top), ray_length)));
+                        // windows and raised doors don't hide what's behind them
+                        if !stops && !is_window && lift == 0.0 {
+                            horizon = horizon.max(top);
+                        }
+                        if stops {
                             distance = local_distance;
                             side = local_side;
                             hit = true;
                             distance_multiplier = local_distance_multiplier;
+                            exact_ray_length = local_exact.map(|(_, ray_length)| ray_length);
                         }
                         // switch which side we were raycasting from to take the fract part to know where the texture was hit
                         let mut fract: f32;
-                        if local_side == 1 {
+                        if let Some((exact_fract, _)) = local_exact {
+                            fract = exact_fract;
+                        } else if local_side == 1 {
                             fract = local_intersection_coord.x.fract();
                         } else {
                             fract = local_intersection_coord.y.fract();
                         }
 
-                        let texture_type;
+                        let mut texture_type = material.wall_texture(segment_index) as i32;
+                        let kind;
 
                         // has door bit set
                         if is_door {
-                            hit_type = 0x2 as i8;
-                            texture_type = TextureType::DOOR as i32;
+                            kind = HitKind::Door;
+                            if texture_type == 0 {
+                                texture_type = TextureType::DOOR as i32;
+                            }
                             // since we'd like the texture to match the width
                             fract -= local_offset;
                             fract /= local_width;
                         } else if is_window {
-                            hit_type = 0x3 as i8;
-                            texture_type = TextureType::WINDOW as i32;
+                            kind = HitKind::Window;
+                            if texture_type == 0 {
+                                texture_type = TextureType::WINDOW as i32;
+                            }
                             // since we'd like the texture to match the width
                             fract -= local_offset;
                             fract /= local_width;
+                        } else if portals.get(map_x, map_y, segment_index as u8).is_some() {
+                            kind = HitKind::Portal;
+                            if texture_type == 0 {
+                                texture_type = TextureType::WALL as i32;
+                            }
+                        } else if material.is_mirror(segment_index) {
+                            kind = HitKind::Mirror;
+                            if texture_type == 0 {
+                                texture_type = TextureType::WALL as i32;
+                            }
                         } else {
-                            hit_type = 1;
-                            texture_type = TextureType::WALL as i32;
+                            kind = HitKind::Wall;
+                            if texture_type == 0 {
+                                texture_type = TextureType::WALL as i32;
+                            }
                         }
 
-                        // add to visible sprites
-                        if !skip_sprites_and_writes {
-                            window_sprites.push(Sprite {
-                                x: local_intersection_coord.x,
-                                y: local_intersection_coord.y,
-                                angle: 0,
-                                height: 100,
-                                r#type: texture_type as i32,
-                                column: column as u32,
-                                distance: local_distance,
-                                distance_fixed: 0,
-                                dx: 0.,
-                                dy: 0.,
-                                fract,
-                            });
+                        let surface = RayHit {
+                            x: local_intersection_coord.x,
+                            y: local_intersection_coord.y,
+                            perp_distance: ray_length,
+                            distance: local_distance,
+                            map_x,
+                            map_y,
+                            segment: Some(segment_index as u8),
+                            side: local_side as u8,
+                            normal_x: local_normal.0,
+                            normal_y: local_normal.1,
+                            texture: texture_type,
+                            u: fract,
+                            height: wall_height,
+                            lift,
+                            kind,
+                            bounces: 0,
+                            portals: 0,
+                        };
+                        if stops {
+                            segment_hit = Some(surface);
+                        }
+                        if max_surfaces > 0 {
+                            surfaces.push(surface);
                         }
                     }
                 }
@@ -485,16 +697,48 @@ pub fn raycast_column(
             }
         }
 
-        // handle thick wall
-        if value == 1 {
-            hit = true;
+        // a wall being pushed through the cell, at its current position
+        if let Some(pushed) = push_walls.hit(
+            map_x,
+            map_y,
+            (position.x, position.y),
+            (ray_dir_x, ray_dir_y),
+        ) {
+            if segment_hit.is_none_or(|other| pushed.distance < other.distance) {
+                hit = true;
+                side = pushed.side as i32;
+                exact_ray_length = Some(pushed.perp_distance);
+                segment_hit = Some(pushed);
+            }
+            if max_surfaces > 0 {
+                surfaces.push(pushed);
+            }
+        }
+
+        // handle thick wall, which is seen past while taller ones may show above it
+        if cell.is_solid() && !hit {
+            let material = CellMaterial::from_u64(materials.get(map_x, map_y));
+            let wall_height = material.wall_height(0);
+            let top = (wall_height - position.z) / cell_ray_length;
+            if stop_at_first_hit
+                || wall_height >= tallest_wall
+                || material.is_mirror(0)
+                || !may_show_past(horizon.max(top), cell_ray_length)
+            {
+                hit = true;
+            } else {
+                horizon = horizon.max(top);
+                if max_surfaces > 0 {
+                    surfaces.push(solid_hit(map_x, map_y, side, cell_ray_length));
+                }
+            }
         }
 
         // only add coord if sprites exist in it;
         // TODO: check more smartly
-        if !skip_sprites_and_writes && column % 5 == 0 {
+        if column % 5 == 0 {
             if let Some(_) = sprites_map.get(&(map_x, map_y)) {
-                let _ = met_coords.push((map_x, map_y));
+                let _ = met_coords.push((map_x, map_y, ViewTransform::IDENTITY));
             }
         }
 
@@ -506,74 +750,282 @@ pub fn raycast_column(
         // jump to next map square, either in x-direction, or in y-direction;
         // post-increment so we don't miss out on content in the immediate coordinate we're standing in
         if side_dist_x < side_dist_y {
+            cell_ray_length = side_dist_x;
             side_dist_x += delta_dist_x;
             map_x += step_x as i32;
             side = 0;
         } else {
+            cell_ray_length = side_dist_y;
             side_dist_y += delta_dist_y;
             map_y += step_y as i32;
             side = 1;
         }
-
-        remaining_range -= 1;
     }
 
     // Calculate distance of perpendicular ray (Euclidean distance would give fisheye effect!)
-    if side == 0 {
+    if let Some(ray_length) = exact_ray_length {
+        perp_wall_dist = ray_length;
+    } else if side == 0 {
         perp_wall_dist += side_dist_x - delta_dist_x;
     } else {
         perp_wall_dist += side_dist_y - delta_dist_y;
     }
 
-    perp_wall_dist = perp_wall_dist * position.plane_y_initial;
+    let ray_hit = hit.then(|| match segment_hit {
+        Some(segment_hit) => RayHit {
+            perp_distance: perp_wall_dist,
+            ..segment_hit
+        },
+        // a solid cell, entered through the side the ray last crossed
+        None => solid_hit(map_x, map_y, side, perp_wall_dist),
+    });
+
+    // a mirror the ray stopped at sends it on, reflected, and a portal from the segment it's
+    // linked to; what it runs into after is at its real position, with the distances along the
+    // whole path
+    let onward = ray_hit
+        .filter(|_| max_bounces > 0)
+        .and_then(|hit| match hit.kind {
+            HitKind::Mirror => {
+                let normal = (hit.normal_x, hit.normal_y);
+                let along_normal = ray_dir_x * normal.0 + ray_dir_y * normal.1;
+                Some((
+                    // a hair in front of the mirror, so it isn't hit again
+                    (hit.x + normal.0 * MIRROR_GAP, hit.y + normal.1 * MIRROR_GAP),
+                    (
+                        ray_dir_x - 2.0 * along_normal * normal.0,
+                        ray_dir_y - 2.0 * along_normal * normal.1,
+                    ),
+                    hit.perp_distance,
+                    hit.distance,
+                    ViewTransform::across((hit.x, hit.y), normal, hit.distance),
+                ))
+            }
+            HitKind::Portal => {
+                // the ray goes on from where it crosses the middle of the portal
+                let (view, ray_length) = portals.enter(
+                    cells,
+                    &hit,
+                    (position.x, position.y),
+                    (ray_dir_x, ray_dir_y),
+                )?;
+                let through = view.inverse();
+                let (dir_x, dir_y) = through.turn((ray_dir_x, ray_dir_y));
+                let (x, y) = through.apply((
+                    position.x + ray_dir_x * ray_length,
+                    position.y + ray_dir_y * ray_length,
+                ));
+                // a hair past the linked segment, so it isn't hit on the way out
+                let gap = MIRROR_GAP / dir_x.hypot(dir_y);
+                Some((
+                    (x + dir_x * gap, y + dir_y * gap),
+                    (dir_x, dir_y),
+                    ray_length,
+                    ray_length * ray_dir_x.hypot(ray_dir_y),
+                    view,
+                ))
+            }
+            _ => None,
+        });
 
-    let col_data = [0, column, 0, 0, 0, hit as i32, hit_type as i32];
+    let mut onward_surfaces: SmallVec<[RayHit; 2]> = SmallVec::new();
+    if let Some(((x, y), (dir_x, dir_y), perp_distance, distance, view)) = onward {
+        let onward_position = Position {
+            x,
+            y,
+            map_x: x.floor() as i32,
+            map_y: y.floor() as i32,
+            dir_x,
+            dir_y,
+            plane_x: 0.0,
+            plane_y: 0.0,
+            ..*position
+        };
+
+        let onward_cast = raycast_column(
+            column,
+            &onward_position,
+            cells,
+            materials,
+            doors,
+            push_walls,
+            portals,
+            width,
+            (max_ray_length - perp_distance) * ray_dir_x.hypot(ray_dir_y),
+            wall_texture_width,
+            Some(sprites_map),
+            max_surfaces,
+            stop_at_first_hit,
+            tallest_wall,
+            max_bounces - 1,
+        );
 
-    (
+        met_coords.extend(
+            onward_cast
+                .met_coords
+                .into_iter()
+                .map(|(x, y, inner)| (x, y, view.after(&inner))),
+        );
+        // a mirror or portal the ray went on from again isn't seen, what's in it is
+        let went_on = onward_cast
+            .ray_hit
+            .filter(|hit| matches!(hit.kind, HitKind::Mirror | HitKind::Portal) && max_bounces > 1);
+        onward_surfaces = onward_cast
+            .surfaces
+            .into_iter()
+            .filter(|surface| Some(*surface) != went_on)
+            .map(|surface| RayHit {
+                perp_distance: surface.perp_distance + perp_distance,
+                distance: surface.distance + distance,
+                bounces: surface.bounces + view.bounces,
+                portals: surface.portals + view.portals,
+                ..surface
+            })
+            .collect();
+    }
+
+    if max_surfaces > 0 {
+        surfaces.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if let Some(ray_hit) = ray_hit {
+            // segments of the last cell are tried in their order, so some may lie behind the hit
+            surfaces.retain(|surface| surface.distance < ray_hit.distance);
+            // the zbuffer is at the hit, so it's kept over the surfaces in front of it
+            surfaces.truncate(max_surfaces - 1);
+            surfaces.push(ray_hit);
+        }
+        // all past the hit, nearest first
+        surfaces.extend(onward_surfaces);
+        surfaces.truncate(max_surfaces);
+    }
+
+    perp_wall_dist = perp_wall_dist * position.plane_y_initial;
+
+    ColumnCast {
         perp_wall_dist,
-        col_data,
-        met_coords.to_vec(),
-        window_sprites,
-    )
+        ray_hit,
+        met_coords,
+        surfaces,
+    }
+}
+
+/// A single ray for tests: what it casts against and how far it goes on, with the rest as
+/// `castRay` has it. Override fields with `..TestRay::new(cells)`.
+#[cfg(test)]
+pub(crate) struct TestRay<'a> {
+    pub cells: MapGrid<'a>,
+    pub materials: MapGrid<'a>,
+    pub portals: &'a Portals,
+    pub sprites_map: Option<&'a HashMap<(i32, i32), Vec<Sprite>>>,
+    pub max_surfaces: usize,
+    pub stop_at_first_hit: bool,
+    pub max_bounces: u32,
 }
 
+#[cfg(test)]
+impl<'a> TestRay<'a> {
+    pub fn new(cells: MapGrid<'a>) -> Self {
+        static NO_PORTALS: Portals = Portals::EMPTY;
+
+        TestRay {
+            cells,
+            materials: MapGrid::EMPTY,
+            portals: &NO_PORTALS,
+            sprites_map: None,
+            max_surfaces: 0,
+            stop_at_first_hit: true,
+            max_bounces: 0,
+        }
+    }
+
+    /// Casts from (x, y) along (dir_x, dir_y) at standing height, see `raycast_column`.
+    pub fn cast(&self, x: f32, y: f32, dir_x: f32, dir_y: f32) -> ColumnCast {
+        let position = Position::looking_along(x, y, dir_x, dir_y, STANDING_EYE_HEIGHT);
+
+        // without a camera plane every column looks along dir, and column 0 collects sprite cells
+        raycast_column(
+            0,
+            &position,
+            self.cells,
+            self.materials,
+            &DoorStates::EMPTY,
+            &PushWalls::EMPTY,
+            self.portals,
+            2,
+            20.0,
+            0,
+            self.sprites_map,
+            self.max_surfaces,
+            self.stop_at_first_hit,
+            self.materials.tallest_wall(),
+            self.max_bounces,
+        )
+    }
+}
+
+// surfaces drawn per column, the farther ones are dropped past this
+const MAX_WALL_SURFACES: usize = 16;
+// how far in front of a mirror or past a linked segment a ray goes on from, in cells
+const MIRROR_GAP: f32 = 1e-3;
+
 #[inline(never)]
 #[no_mangle]
 pub fn draw_walls_raycast(
     position: &Position,
     zbuffer: &mut [f32],
-    map_data: &[u64],
-    map_width: usize, // Needed to index into 1D map
+    mirror_depths: &mut [f32], // per column, how deep the reflection seen there goes; 0 for none
+    cells: MapGrid,
+    materials: MapGrid,
+    doors: &DoorStates,
+    push_walls: &PushWalls,
+    portals: &Portals,
+    tallest_wall: f32, // of the materials, see `WasmMapLayers::tallest_wall`
     width: i32,
-    range: i8,
+    max_distance: f32,
     wall_texture_width: i32,
+    mirror_bounces: u32,
     sprites_map: &WasmStripePerCoordMap,
     found_sprites: &mut SmallVec<[Sprite; 1024]>,
 ) {
-    let data: Vec<(f32, [i32; 7], Vec<(i32, i32)>, SmallVec<[Sprite; 2]>)> = (0..width)
+    let data: Vec<ColumnCast> = (0..width)
         .into_par_iter()
         .map(|column| {
-            let (perp_wall_dist, col_data, met_coords, window_sprites) = raycast_column(
+            raycast_column(
                 column,
                 position,
-                map_data,
-                map_width,
+                cells,
+                materials,
+                doors,
+                push_walls,
+                portals,
                 width,
-                range,
+                max_distance,
                 wall_texture_width,
                 Some(&sprites_map.get_map()),
+                MAX_WALL_SURFACES,
                 false,
-                false,
-            );
-
-            (perp_wall_dist, col_data, met_coords, window_sprites)
+                tallest_wall,
+                mirror_bounces,
+            )
         })
         .collect();
 
     let uniqued_met_coords: HashSet<(i32, i32)> = data
         .iter()
-        .flat_map(|(_, _, met_coords, _)| met_coords.iter().copied())
+        .flat_map(|cast| cast.met_coords.iter())
+        .filter(|(_, _, view)| !view.is_seen_through())
+        .map(|(x, y, _)| (*x, *y))
         .collect();
+    // cells seen in mirrors and through portals, once per way they're seen
+    let mut seen_through_met_coords: Vec<(i32, i32, ViewTransform)> = vec![];
+    for coord in data
+        .iter()
+        .flat_map(|cast| cast.met_coords.iter())
+        .filter(|(_, _, view)| view.is_seen_through())
+    {
+        if !seen_through_met_coords.contains(coord) {
+            seen_through_met_coords.push(*coord);
+        }
+    }
 
     let sprites_map = sprites_map.get_map();
 
@@ -584,6 +1036,21 @@ pub fn draw_walls_raycast(
             found_sprites.extend((*sprite_list).clone());
         }
     }
+    for (map_x, map_y, view) in &seen_through_met_coords {
+        if let Some(sprite_list) = sprites_map.get(&(*map_x, *map_y)) {
+            found_sprites.extend(sprite_list.iter().map(|sprite| {
+                let (x, y) = view.apply((sprite.x, sprite.y));
+                Sprite {
+                    x,
+                    y,
+                    bounces: view.bounces,
+                    portals: view.portals,
+                    entry_distance: view.entry_distance,
+                    ..sprite.clone()
+                }
+            }));
+        }
+    }
     // since sprites don't have a distance yet, do it now
     found_sprites.iter_mut().for_each(|sprite| {
         let local_distance = Euclidean.distance(
@@ -593,9 +1060,56 @@ pub fn draw_walls_raycast(
         sprite.distance = local_distance;
     });
 
-    for (idx, (perp_wall_dist, _, _, window_sprites)) in data.iter().enumerate() {
-        zbuffer[idx] = *perp_wall_dist;
-        found_sprites.extend((*window_sprites).clone());
+    // every wall, door and window along a column is drawn as a one column sprite, farthest first,
+    // so the ones that can be seen through are blended over what's behind them
+    for (column, cast) in data.iter().enumerate() {
+        let ColumnCast {
+            perp_wall_dist,
+            ray_hit,
+            surfaces,
+            ..
+        } = cast;
+        zbuffer[column] = *perp_wall_dist;
+        mirror_depths[column] = 0.0;
+
+        let camera_x = (2.0 * (column as f32) / (width as f32)) - 1.0;
+        let ray_dir_x = position.dir_x + position.plane_x * camera_x;
+        let ray_dir_y = position.dir_y + position.plane_y * camera_x;
+
+        found_sprites.extend(surfaces.iter().map(|surface| {
+            // what's seen in a mirror or through a portal is drawn where it appears to be,
+            // behind it
+            let (x, y) = if surface.bounces > 0 || surface.portals > 0 {
+                mirror_depths[column] =
+                    mirror_depths[column].max(surface.perp_distance * position.plane_y_initial);
+                (
+                    position.x + ray_dir_x * surface.perp_distance,
+                    position.y + ray_dir_y * surface.perp_distance,
+                )
+            } else {
+                (surface.x, surface.y)
+            };
+
+            Sprite {
+                x,
+                y,
+                angle: 0,
+                height: (surface.height * 100.0) as i32,
+                lift: surface.lift,
+                r#type: surface.texture,
+                is_wall: true,
+                column: column as u32,
+                distance: surface.distance,
+                distance_fixed: 0,
+                index: 0,
+                bounces: surface.bounces,
+                portals: surface.portals,
+                entry_distance: ray_hit.map_or(0.0, |entry| entry.distance),
+                dx: 0.,
+                dy: 0.,
+                fract: surface.u,
+            }
+        }));
     }
 }
 
@@ -604,21 +1118,16 @@ pub fn draw_walls_raycast(
 pub fn draw_ceiling_floor_raycast(
     position: &Position,
     img_slice: &mut [u8],
-    floor_texture_array: &[u8],
-    ceiling_texture_array: &[u8],
-    road_texture_array: &[u8],
+    textures: &[Option<Texture>], // indexed by texture id
     width: i32,
     height: i32,
     light_range: i32,
     map_light: i32,
-    floor_texture_width: i32,
-    floor_texture_height: i32,
-    ceiling_texture_width: i32,
-    ceiling_texture_height: i32,
-    road_texture_width: i32,
-    road_texture_height: i32,
-    map_data: &[u64],
-    map_width: usize,
+    max_distance: f32,
+    cells: MapGrid,
+    materials: MapGrid,
+    draw_floor: bool,
+    draw_ceiling: bool,
 ) {
     let ray_dir_x0 = position.dir_x - position.plane_x;
     let ray_dir_y0 = position.dir_y - position.plane_y;
@@ -628,37 +1137,21 @@ pub fn draw_ceiling_floor_raycast(
     let ray_dir_y_dist = ray_dir_y1 - ray_dir_y0;
 
     let half_height = (height / 2) as i32;
-    let floor_cam_z = half_height + position.z as i32;
-    let ceiling_cam_z = half_height - position.z as i32;
+    // how far below and above the eyes the floor and the ceiling are, in wall heights
+    let floor_cam_z = position.z;
+    let ceiling_cam_z = 1.0 - position.z;
     let middle_view_y = half_height + position.pitch;
 
     // if we're above the ceiling
-    let mut is_above_ceiling = false;
-    if ceiling_cam_z < 0 {
-        is_above_ceiling = true;
-        // move the floor up so it appears as a ceiling from the top
-        // floor_cam_z -= height as i32;
-    }
+    let is_above_ceiling = ceiling_cam_z < 0.0;
+    // e.g. the floor of the layer above us
+    let is_below_floor = floor_cam_z < 0.0;
 
-    let height_ratio = height as f32 / width as f32;
-    let distance_divider = (2.0 * height_ratio) * position.plane_y_initial;
+    // pixels a wall height spans one plane length away, the same as for walls and sprites
+    let focal_length = (width / 2) as f32 / position.plane_y_initial;
 
-    let road_texture_data = Texture {
-        data: road_texture_array,
-        width: road_texture_width,
-        height: road_texture_height,
-    };
-    let ceiling_texture_data = Texture {
-        data: ceiling_texture_array,
-        width: ceiling_texture_width,
-        height: ceiling_texture_height,
-    };
-    let floor_texture_data = Texture {
-        data: floor_texture_array,
-        width: floor_texture_width,
-        height: floor_texture_height,
-    };
     let map_light_fixed = map_light << FIXED_SHIFT;
+    let dir_length = position.dir_x.hypot(position.dir_y);
 
     img_slice
         .par_chunks_mut((width * 4) as usize)
@@ -669,7 +1162,10 @@ pub fn draw_ceiling_floor_raycast(
             // if we're drawing the bottom half of the screen
             let is_floor = y > middle_view_y;
 
-            if !is_floor && is_above_ceiling {
+            if !is_floor && (is_above_ceiling || !draw_ceiling) {
+                return;
+            }
+            if is_floor && (is_below_floor || !draw_floor) {
                 return;
             }
             let p = if is_floor {
@@ -679,9 +1175,18 @@ pub fn draw_ceiling_floor_raycast(
             };
             let cam_z = if is_floor { floor_cam_z } else { ceiling_cam_z };
 
-            let row_distance = cam_z as f32 / (p as f32 * distance_divider);
+            let row_distance = cam_z * focal_length / p as f32;
             let row_distance_fixed = to_fixed(row_distance);
 
+            // past the view distance the sky shows, and the rows before it fade into it
+            let opacity = fixed_mul(
+                distance_opacity(row_distance * dir_length, max_distance),
+                256,
+            ) as u32;
+            if opacity == 0 {
+                return;
+            }
+
             let alpha_fixed =
                 (FIXED_ONE - ((row_distance_fixed / light_range) - map_light_fixed)).max(0);
             let alpha = fixed_mul(alpha_fixed, 256);
@@ -709,18 +1214,20 @@ pub fn draw_ceiling_floor_raycast(
                 let map_x = world_x >> FIXED_SHIFT;
                 let map_y = world_y >> FIXED_SHIFT;
 
-                let value = get_grid_value(map_x, map_y, map_width as i32, map_data);
-                let has_ceiling = has_bit_set(value, 1);
-                let has_road = has_bit_set(value, 3);
-
-                let tex = match (is_floor, has_road, has_ceiling) {
-                    (true, false, true) => Some(&floor_texture_data),
-                    (false, _, true) => Some(&ceiling_texture_data),
-                    (true, true, _) => Some(&road_texture_data),
-                    _ => None,
+                let cell = Cell::from_u64(cells.get(map_x, map_y));
+                let material = CellMaterial::from_u64(materials.get(map_x, map_y));
+
+                // a texture set in the material layer wins over the cell bits
+                let texture_id = match (is_floor, cell.has_road, cell.has_ceiling) {
+                    (true, _, _) if material.floor_texture != 0 => material.floor_texture,
+                    (false, _, _) if material.ceiling_texture != 0 => material.ceiling_texture,
+                    (true, false, true) => TextureType::FLOOR as u8,
+                    (false, _, true) => TextureType::CEILING as u8,
+                    (true, true, _) => TextureType::ROAD as u8,
+                    _ => 0,
                 };
 
-                if let Some(tex) = tex {
+                if let Some(tex) = &textures[texture_id as usize] {
                     let frac_x = (world_x & (FIXED_ONE - 1)) as usize;
                     let frac_y = (world_y & (FIXED_ONE - 1)) as usize;
 
@@ -734,6 +1241,15 @@ pub fn draw_ceiling_floor_raycast(
                     // let g = (texel[1] as u16 * alpha as u16) >> 8;
                     // let b = (texel[2] as u16 * alpha as u16) >> 8;
 
+                    if opacity < 256 {
+                        for (channel, texel) in pixel.iter_mut().zip(texel) {
+                            let color = (*texel as u32 * alpha as u32 >> 8).min(255);
+                            *channel =
+                                ((color * opacity + *channel as u32 * (256 - opacity)) >> 8) as u8;
+                        }
+                        return;
+                    }
+
                     pixel[0] = (texel[0] as u16 * alpha as u16 >> 8) as u8;
                     pixel[1] = (texel[1] as u16 * alpha as u16 >> 8) as u8;
                     pixel[2] = (texel[2] as u16 * alpha as u16 >> 8) as u8;
@@ -749,7 +1265,6 @@ pub fn translate_coordinate_to_camera(
     height_multiplier: f32,
     width: i32,
     height: i32,
-    aspect_ratio: f32,
     inv_det: f32,
 ) -> TranslationResult {
     let half_height = height / 2;
@@ -763,9 +1278,9 @@ pub fn translate_coordinate_to_camera(
 
     let screen_x = ((half_width as f32) * (1.0 + (transform_x / transform_y))) as i32;
 
-    // to control the pitch/jump
-    let v_move_screen =
-        position.pitch + ((position.z as f32) / (transform_y * (aspect_ratio * 2.0))) as i32;
+    // to control the pitch/jump; eyes above standing height see things lower
+    let v_move_screen = position.pitch
+        + ((position.z - STANDING_EYE_HEIGHT) * half_width as f32 / transform_y) as i32;
 
     let y_height_before_adjustment = (half_width as f32 / (transform_y)) as i32;
     // since each sprite has a certain height (e.g. 1.1 of the 1 normal height), we multiply by that
@@ -793,11 +1308,15 @@ pub fn draw_sprites_wasm(
     width: i32,
     height: i32,
     zbuffer: &mut [f32],
+    mirror_depths: &[f32],
     light_range: i32,
     map_light: i32,
+    max_distance: f32,
+    mirror_tint: u32,
     sprites_texture_map: &WasmTextureMap,
     texture_array: &WasmTextureMetaMap,
     found_sprites: &mut SmallVec<[Sprite; 1024]>,
+    pick_buffer: &mut WasmPickBuffer,
 ) {
     found_sprites.iter_mut().for_each(|sprite| {
         let dx = sprite.x - position.x;
@@ -805,14 +1324,31 @@ pub fn draw_sprites_wasm(
         sprite.dx = dx;
         sprite.dy = dy;
     });
-    // since we should draw those in the distance first, we sort them
+    // since we should draw those in the distance first, we sort them;
+    // what's seen in a mirror goes right after the mirror, so nearer things still cover it
+    let draw_distance = |sprite: &Sprite| {
+        if sprite.is_seen_through() {
+            sprite.entry_distance - MIRROR_GAP
+        } else {
+            sprite.distance
+        }
+    };
     found_sprites.sort_unstable_by(|a, b| {
-        b.distance.partial_cmp(&a.distance).unwrap() // sort descending (farther first)
+        draw_distance(b)
+            .partial_cmp(&draw_distance(a))
+            .unwrap()
+            .then(b.distance.partial_cmp(&a.distance).unwrap()) // sort descending (farther first)
     });
 
+    let tint_color = mirror_tint >> 8;
+    let tint_alpha = (mirror_tint & 0xFF) as f32 / 255.0;
+    // each bounce blends in some more of the tint
+    let tint_strength =
+        |bounces: u8| ((1.0 - (1.0 - tint_alpha).powi(bounces as i32)) * 256.0) as i32;
+
     // for usage in translate_coordinate_to_camera
-    let aspect_ratio = height as f32 / width as f32;
     let inv_det = (position.plane_x * position.dir_y - position.dir_x * position.plane_y).abs();
+    let dir_length = position.dir_x.hypot(position.dir_y);
 
     let sprite_parts_collected: Vec<SpritePart> = found_sprites
         .into_iter()
@@ -824,7 +1360,6 @@ pub fn draw_sprites_wasm(
                 sprite.height as f32 / 100.0,
                 width,
                 height,
-                aspect_ratio,
                 inv_det,
             );
 
@@ -833,27 +1368,50 @@ pub fn draw_sprites_wasm(
             // ensure sprites are always at least a little bit visible - alpha 1 is all black
             let alpha_i = (FIXED_ONE - to_fixed(alpha)).clamp(FIXED_ONE / 8, FIXED_ONE) as i32;
 
-            let texture_meta = texture_array.get(sprite.r#type).unwrap();
-
-            if sprite.r#type == TextureType::WINDOW as i32
-                || sprite.r#type == TextureType::DOOR as i32
-                || sprite.r#type == TextureType::WALL as i32
+            // a wall segment whose texture isn't loaded shows the default wall texture
+            let texture_type = if sprite.is_wall
+                && (texture_array.get(sprite.r#type).is_none()
+                    || !sprites_texture_map
+                        .get_map()
+                        .contains_key(&(sprite.r#type, 0)))
             {
+                TextureType::WALL as i32
+            } else {
+                sprite.r#type
+            };
+            let texture_meta = texture_array.get(texture_type).unwrap();
+
+            // along the view direction, the same as the floor
+            let view_distance =
+                (sprite.dx * position.dir_x + sprite.dy * position.dir_y) / dir_length;
+            let opacity = distance_opacity(view_distance, max_distance);
+
+            // walls are drawn column by column, with the texture of the segment that was hit
+            if sprite.is_wall {
                 let texture_data = sprites_texture_map
                     .get_map()
-                    .get(&(sprite.r#type, 0))
+                    .get(&(texture_type, 0))
                     .unwrap();
 
                 let texture_x: i32 = (sprite.fract * texture_meta.width as f32) as i32;
 
-                let inv_sprite_height = texture_meta.height * FIXED_ONE / projection.full_height;
+                // one texture per wall height, so low walls are cut off and tall ones repeat it
+                let inv_sprite_height =
+                    (texture_meta.height as i64 * FIXED_ONE as i64 * sprite.height as i64
+                        / (100 * projection.full_height.max(1) as i64)) as i32;
                 let inv_sprite_width = 1 * FIXED_ONE / (1);
+                // a raised door's top disappears into the wall above, its bottom shows higher up
+                let hidden_rows = (projection.full_height as f32 * sprite.lift) as i32;
                 return SpritePart {
-                    sprite_type: sprite.r#type,
+                    sprite_type: texture_type,
+                    opacity,
                     sprite_left_x: sprite.column,
                     width: 1,
                     screen_y_ceiling: projection.screen_y_ceiling,
-                    height: projection.full_height,
+                    height: projection.full_height - hidden_rows,
+                    hidden_rows,
+                    tint: tint_color,
+                    tint_strength: tint_strength(sprite.bounces),
                     tex_x1: texture_x,
                     tex_width: 1,
                     alpha: alpha_i,
@@ -889,10 +1447,19 @@ pub fn draw_sprites_wasm(
             let mut draw_start_x = (-sprite_width / 2 + projection.screen_x).max(0);
             let mut draw_end_x = (sprite_width / 2 + projection.screen_x).min(width - 1);
 
+            // sprites seen in a mirror only show where the mirror is, in front of what it reflects
+            let is_hidden = |column: usize| {
+                if sprite.is_seen_through() {
+                    projection.distance >= mirror_depths[column]
+                } else {
+                    projection.distance >= zbuffer[column]
+                }
+            };
+
             // advance the non-visible parts
             let mut idx_start = draw_start_x;
             while idx_start < width as i32
-                && (projection.distance >= zbuffer[idx_start as usize])
+                && is_hidden(idx_start as usize)
                 && draw_start_x + 1 < draw_end_x
             {
                 draw_start_x += 1;
@@ -900,10 +1467,7 @@ pub fn draw_sprites_wasm(
             }
 
             let mut idx_end = draw_end_x;
-            while idx_end >= 0
-                && (projection.distance >= zbuffer[idx_end as usize])
-                && draw_end_x - 1 > draw_start_x
-            {
+            while idx_end >= 0 && is_hidden(idx_end as usize) && draw_end_x - 1 > draw_start_x {
                 draw_end_x -= 1;
                 idx_end -= 1;
             }
@@ -912,14 +1476,34 @@ pub fn draw_sprites_wasm(
             let tex_x1 = ((draw_start_x - to_remove_texture) * texture_meta.width) / sprite_width;
             let tex_width = ((draw_end_x - draw_start_x) * texture_meta.width) / sprite_width;
 
+            // reflections aren't picked, the sprites themselves are
+            if !sprite.is_seen_through() {
+                pick_buffer.add_sprite(SpriteHit {
+                    index: sprite.index,
+                    r#type: sprite.r#type,
+                    x: sprite.x,
+                    y: sprite.y,
+                    distance: sprite.distance,
+                    left: draw_start_x,
+                    right: draw_end_x,
+                    top: projection.screen_y_ceiling,
+                    bottom: projection.screen_y_ceiling + projection.full_height,
+                    depth: projection.distance,
+                });
+            }
+
             let inv_sprite_height = texture_meta.height * FIXED_ONE / projection.full_height;
             let inv_sprite_width = tex_width * FIXED_ONE / (draw_end_x - draw_start_x).max(1);
             SpritePart {
                 sprite_type: sprite.r#type,
+                opacity,
                 sprite_left_x: draw_start_x as u32,
                 width: draw_end_x - draw_start_x,
                 screen_y_ceiling: projection.screen_y_ceiling,
                 height: projection.full_height,
+                hidden_rows: 0,
+                tint: tint_color,
+                tint_strength: tint_strength(sprite.bounces),
                 tex_x1,
                 tex_width,
                 alpha: alpha_i,
@@ -943,8 +1527,10 @@ pub fn draw_sprites_wasm(
                     continue;
                 }
 
-                let dy = y - sprite.screen_y_ceiling;
-                let tex_y = ((dy * sprite.inv_sprite_height) >> FIXED_SHIFT) as i32;
+                let dy = y - sprite.screen_y_ceiling + sprite.hidden_rows;
+                // in i64, since walls taller than one texture overflow
+                let tex_y = ((dy as i64 * sprite.inv_sprite_height as i64) >> FIXED_SHIFT) as i32
+                    % sprite.full_texture_height;
                 let y_tex_idx = tex_y * sprite.full_texture_width;
 
                 for dx in 0..sprite.width {
@@ -955,7 +1541,7 @@ pub fn draw_sprites_wasm(
                     let texel =
                         unsafe { sprite.full_texture_data.get_unchecked(tex_idx..tex_idx + 4) };
 
-                    let a = texel[3] as u16;
+                    let a = ((texel[3] as i32 * sprite.opacity) >> FIXED_SHIFT) as u16;
                     if a == 0 {
                         continue;
                     }
@@ -963,6 +1549,18 @@ pub fn draw_sprites_wasm(
                     let mut g = ((texel[1] as i32 * sprite.alpha) >> FIXED_SHIFT) as u8;
                     let mut b = ((texel[2] as i32 * sprite.alpha) >> FIXED_SHIFT) as u8;
 
+                    if sprite.tint_strength > 0 {
+                        let blend = |channel: u8, shift: u32| {
+                            let tint = ((sprite.tint >> shift) & 0xFF) as i32;
+                            ((channel as i32 * (256 - sprite.tint_strength)
+                                + tint * sprite.tint_strength)
+                                >> 8) as u8
+                        };
+                        r = blend(r, 16);
+                        g = blend(g, 8);
+                        b = blend(b, 0);
+                    }
+
                     let x = sprite.sprite_left_x as i32 + dx;
                     let idx = (x * 4) as usize;
 
@@ -1048,40 +1646,30 @@ pub fn draw_background_image_prescaled(
         });
 }
 
-// move if no wall in front of you
+// how close walking gets to a wall, in cells
+const WALL_MARGIN: f32 = 0.2;
+
+// move the camera if no wall in front of it, turning it if it went through a portal
 #[wasm_bindgen]
 pub fn walk(
-    x: f32,
-    y: f32,
-    dir_x: f32,
-    dir_y: f32,
-    plane_x: f32,
-    plane_y: f32,
-    pitch: i32,
-    z: i32,
-    plane_y_initial: f32,
+    camera: &mut Camera,
     distance: f32,
-    map_array: *mut u64,
-    map_width: i32,
+    layers: &WasmMapLayers,
     width: i32,
-    range: i8,
+    height: i32,
+    max_distance: f32,
     wall_texture_width: i32,
-) -> Float32Array {
-    let position = Position {
-        x,
-        y,
-        dir_x,
-        dir_y,
-        plane_x,
-        plane_y,
-        pitch,
-        z,
-        plane_y_initial,
-        map_x: x.floor() as i32,
-        map_y: y.floor() as i32,
-    };
+) {
+    let position = camera.position(width, height);
+    // the zbuffer distances are in plane lengths
+    let margin = WALL_MARGIN * position.plane_y_initial;
 
-    let map_data = unsafe { from_raw_parts(map_array, (map_width * map_width) as usize) };
+    // only the walls of the layer we're standing on are in the way
+    let layer = layers.layer_at(position.z - STANDING_EYE_HEIGHT);
+    let cells = layers.cells(layer);
+    let doors = layers.doors(layer);
+    let push_walls = layers.push_walls(layer);
+    let portals = layers.portals(layer);
 
     let mut raycast_position = position.clone();
 
@@ -1092,29 +1680,55 @@ pub fn walk(
     }
 
     // raycast middle column to get the distance
-    let (perp_wall_dist, col_data, _, _) = raycast_column(
+    let ColumnCast {
+        perp_wall_dist,
+        ray_hit,
+        ..
+    } = raycast_column(
         (width / 2) as i32,
         &raycast_position,
-        map_data,
-        map_width as usize,
+        cells,
+        MapGrid::EMPTY, // textures don't matter for collisions
+        doors,
+        push_walls,
+        portals,
         width,
-        range,
+        max_distance,
         wall_texture_width,
         None,
+        0,
         true,
-        true,
+        1.0,
+        0,
     );
 
-    let mut x = position.x;
-    let mut y = position.y;
-
-    // if far enough or not a door
-    if perp_wall_dist > 0.2 || (col_data[6] == 2) {
-        x += position.dir_x * distance;
-        y += position.dir_y * distance;
+    let (x, y) = (position.x, position.y);
+
+    // stepping into a portal comes out of the segment it's linked to, past its middle, turned
+    // the same way
+    let step = distance.abs();
+    let ray_dir = (raycast_position.dir_x, raycast_position.dir_y);
+    if let Some((view, crossing)) = ray_hit
+        .filter(|hit| hit.kind == HitKind::Portal && hit.perp_distance <= step)
+        .and_then(|hit| portals.enter(cells, &hit, (x, y), ray_dir))
+    {
+        let through = view.inverse();
+        let along = step.max(crossing + MIRROR_GAP);
+        let (x, y) = through.apply((x + ray_dir.0 * along, y + ray_dir.1 * along));
+        *camera = camera.looking(x, y, through.turn(camera.dir()));
+        return;
+    }
 
-        let result = vec![x, y];
-        return Float32Array::from(result.as_slice());
+    // if far enough, through a door that's open or towards a portal
+    if perp_wall_dist > margin
+        || ray_hit.is_some_and(|hit| {
+            hit.kind == HitKind::Door && doors.is_open(hit.map_x, hit.map_y)
+                || hit.kind == HitKind::Portal
+        })
+    {
+        camera.x += position.dir_x * distance;
+        camera.y += position.dir_y * distance;
+        return;
     }
 
     // since we can't move in both direction, check just y
@@ -1122,23 +1736,27 @@ pub fn walk(
     raycast_position_x.dir_y = 0.0;
 
     // raycast middle column to get the distance
-    let (perp_wall_dist_x, _, _, _) = raycast_column(
+    let perp_wall_dist_x = raycast_column(
         (width / 2) as i32,
         &raycast_position_x,
-        map_data,
-        map_width as usize,
+        cells,
+        MapGrid::EMPTY,
+        doors,
+        push_walls,
+        portals,
         width,
-        range,
+        max_distance,
         wall_texture_width,
         None,
+        0,
         true,
-        true,
-    );
-    if perp_wall_dist_x > 0.2 {
-        x += position.dir_x * distance;
-
-        let result = vec![x, y];
-        return Float32Array::from(result.as_slice());
+        1.0,
+        0,
+    )
+    .perp_wall_dist;
+    if perp_wall_dist_x > margin {
+        camera.x += position.dir_x * distance;
+        return;
     }
 
     // if we weren't able to move x, check if we can move y
@@ -1146,27 +1764,170 @@ pub fn walk(
     raycast_position_y.dir_x = 0.0;
 
     // raycast middle column to get the distance
-    let (perp_wall_dist_y, _, _, _) = raycast_column(
+    let perp_wall_dist_y = raycast_column(
         (width / 2) as i32,
         &raycast_position_y,
-        map_data,
-        map_width as usize,
+        cells,
+        MapGrid::EMPTY,
+        doors,
+        push_walls,
+        portals,
         width,
-        range,
+        max_distance,
         wall_texture_width,
         None,
+        0,
         true,
+        1.0,
+        0,
+    )
+    .perp_wall_dist;
+    if perp_wall_dist_y > margin {
+        camera.y += position.dir_y * distance;
+    }
+}
+
+/// The first wall straight ahead on the layer the camera stands on, e.g. to aim or to interact
+/// with doors; undefined if there's none within `max_distance`.
+#[wasm_bindgen(js_name = castRay)]
+pub fn cast_ray(
+    x: f32,
+    y: f32,
+    dir_x: f32,
+    dir_y: f32,
+    z: f32,
+    layers: &WasmMapLayers,
+    max_distance: f32,
+) -> Option<RayHit> {
+    let position = Position::looking_along(x, y, dir_x, dir_y, z);
+    let layer = layers.layer_at(z - STANDING_EYE_HEIGHT);
+
+    // the middle one of two columns looks straight along dir
+    let ray_hit = raycast_column(
+        1,
+        &position,
+        layers.cells(layer),
+        layers.materials(layer),
+        layers.doors(layer),
+        layers.push_walls(layer),
+        layers.portals(layer),
+        2,
+        max_distance,
+        0,
+        None,
+        0,
         true,
-    );
-    if perp_wall_dist_y > 0.2 {
-        y += position.dir_y * distance;
+        1.0,
+        0,
+    )
+    .ray_hit;
+
+    ray_hit
+}
+
+/// Like `castRay`, but every wall, door and window the ray from `position` passes, nearest
+/// first, up to `limit` of them. It goes on through windows and low walls, so the last one is
+/// the first that can't be seen through unless `max_distance` was reached; it's kept over the
+/// ones in front of it when there are more than `limit`.
+#[wasm_bindgen(js_name = castRaySurfaces)]
+pub fn cast_ray_surfaces(
+    position: &Position,
+    layers: &WasmMapLayers,
+    max_distance: f32,
+    limit: usize,
+) -> Vec<RayHit> {
+    let layer = layers.layer_at(position.z - STANDING_EYE_HEIGHT);
+    // as in `render`, the layer is looked at as if standing that much higher or lower
+    let position = Position {
+        z: position.z - layers.floor_height(layer),
+        ..*position
+    };
 
-        let result = vec![x, y];
-        return Float32Array::from(result.as_slice());
+    let surfaces = raycast_column(
+        1,
+        &position,
+        layers.cells(layer),
+        layers.materials(layer),
+        layers.doors(layer),
+        layers.push_walls(layer),
+        layers.portals(layer),
+        2,
+        max_distance,
+        0,
+        None,
+        limit,
+        false,
+        layers.tallest_wall(layer),
+        0,
+    )
+    .surfaces;
+
+    surfaces.into_vec()
+}
+
+/// Whether `to` can be seen from `from`: the first wall in between, if there is one. Windows
+/// and walls lower than a full wall can be seen through, other walls can't. Doors can be seen
+/// past where they're open, through the gap of a sliding door or under a raised one. Portals
+/// block it like walls.
+pub fn line_of_sight(
+    from: (f32, f32),
+    to: (f32, f32),
+    cells: MapGrid,
+    materials: MapGrid, // for the heights of the walls, may be empty
+    doors: &DoorStates,
+    push_walls: &PushWalls,
+) -> Option<RayHit> {
+    let (dir_x, dir_y) = (to.0 - from.0, to.1 - from.1);
+    let length = dir_x.hypot(dir_y);
+    if length == 0.0 {
+        return None;
     }
 
-    let result = vec![x, y];
-    Float32Array::from(result.as_slice())
+    let position = Position::looking_along(from.0, from.1, dir_x, dir_y, STANDING_EYE_HEIGHT);
+
+    // in lengths of dir, so anything past 1 is behind `to`
+    let ray_hit = raycast_column(
+        1,
+        &position,
+        cells,
+        materials,
+        doors,
+        push_walls,
+        &Portals::EMPTY,
+        2,
+        length,
+        0,
+        None,
+        0,
+        false,
+        1.0, // at eye height, nothing is seen past a full wall
+        0,
+    )
+    .ray_hit;
+
+    ray_hit.filter(|hit| hit.perp_distance <= 1.0)
+}
+
+/// `line_of_sight` between two points on the layer of eyes at height `z`, like the camera's.
+/// Cheap enough to call for every actor every frame.
+#[wasm_bindgen(js_name = lineOfSight)]
+pub fn line_of_sight_js(
+    from_x: f32,
+    from_y: f32,
+    to_x: f32,
+    to_y: f32,
+    z: f32,
+    layers: &WasmMapLayers,
+) -> Option<RayHit> {
+    let layer = layers.layer_at(z - STANDING_EYE_HEIGHT);
+    line_of_sight(
+        (from_x, from_y),
+        (to_x, to_y),
+        layers.cells(layer),
+        layers.materials(layer),
+        layers.doors(layer),
+        layers.push_walls(layer),
+    )
 }
 
 #[wasm_bindgen]
@@ -1191,3 +1952,305 @@ pub fn rotate_view(
     // Avoid heap allocation by creating a fixed-size array
     Float32Array::from(&[new_dir_x, new_dir_y, new_plane_x, new_plane_y][..])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii::AsciiMap;
+    use crate::doors::DoorStyle;
+    use crate::push_walls::Push;
+
+    #[test]
+    fn sees_taller_walls_above_nearer_ones() {
+        let map = AsciiMap::parse(
+            "
+            ######
+            #.#.##
+            ######
+            ",
+        )
+        .unwrap();
+        let mut materials = vec![0u64; map.cells.len()];
+        let mut tall = CellMaterial::new();
+        tall.set_wall_height(0, 2.0);
+        materials[map.width + 4] = tall.to_u64();
+        let ColumnCast {
+            ray_hit, surfaces, ..
+        } = TestRay {
+            materials: MapGrid::Dense {
+                data: &materials,
+                width: map.width,
+                height: map.height,
+            },
+            max_surfaces: 8,
+            stop_at_first_hit: false,
+            ..TestRay::new(map.grid())
+        }
+        .cast(1.5, 1.5, 1.0, 0.0);
+
+        // the full wall is drawn in front of the tall one, which stops the ray
+        assert_eq!(
+            vec![(2, 1.0), (4, 2.0)],
+            surfaces
+                .iter()
+                .map(|surface| (surface.map_x, surface.height))
+                .collect::<Vec<_>>()
+        );
+        let hit = ray_hit.unwrap();
+        assert_eq!((4, 2.0), (hit.map_x, hit.height));
+        assert!((hit.perp_distance - 2.5).abs() < 1e-4);
+        assert!((surfaces[0].perp_distance - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn looks_past_walls_only_while_taller_ones_may_show() {
+        let map = AsciiMap::parse(
+            "
+            ##########
+            #..#######
+            ##########
+            ",
+        )
+        .unwrap();
+        // the tallest wall is off the ray
+        let mut materials = vec![0u64; map.cells.len()];
+        let mut tall = CellMaterial::new();
+        tall.set_wall_height(0, 1.75);
+        materials[0] = tall.to_u64();
+
+        let ColumnCast {
+            ray_hit, surfaces, ..
+        } = TestRay {
+            materials: MapGrid::Dense {
+                data: &materials,
+                width: map.width,
+                height: map.height,
+            },
+            max_surfaces: 8,
+            stop_at_first_hit: false,
+            ..TestRay::new(map.grid())
+        }
+        .cast(1.5, 1.5, 1.0, 0.0);
+
+        // from 4.5 away on, a wall that tall wouldn't rise above the wall 1.5 away
+        assert_eq!(Some(6), ray_hit.map(|hit| hit.map_x));
+        assert_eq!(
+            vec![3, 4, 5, 6],
+            surfaces
+                .iter()
+                .map(|surface| surface.map_x)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn draws_walls_with_unloaded_textures_as_default_walls() {
+        let position = Camera::new(0.5, 0.5, 0.0, 90.0, 0, STANDING_EYE_HEIGHT).position(4, 4);
+        let mut metas = WasmTextureMetaMap::new();
+        metas.populate_from_array(TextureType::WALL as i32, 1, 1, 1);
+        let mut textures = WasmTextureMap::new();
+        textures.populate_from_array(TextureType::WALL as i32, 0, &[200, 100, 50, 255]);
+
+        let map = AsciiMap::parse("..#").unwrap();
+        let mut material = CellMaterial::new();
+        material.set_wall_texture(0, 42); // never loaded
+        let materials = [0, 0, material.to_u64()];
+        let wall = TestRay {
+            materials: MapGrid::Dense {
+                data: &materials,
+                width: map.width,
+                height: map.height,
+            },
+            ..TestRay::new(map.grid())
+        }
+        .cast(0.5, 0.5, 1.0, 0.0)
+        .ray_hit
+        .unwrap();
+
+        // as `draw_walls_raycast` passes it on, in the middle column
+        let mut found_sprites: SmallVec<[Sprite; 1024]> = SmallVec::new();
+        found_sprites.push(Sprite {
+            x: wall.x,
+            y: wall.y,
+            fract: wall.u,
+            dx: 0.0,
+            dy: 0.0,
+            angle: 0,
+            height: (wall.height * 100.0) as i32,
+            lift: wall.lift,
+            r#type: wall.texture,
+            is_wall: true,
+            column: 2,
+            distance: wall.distance,
+            distance_fixed: 0,
+            index: 0,
+            bounces: 0,
+            portals: 0,
+            entry_distance: wall.distance,
+        });
+        let mut img = vec![0u8; 4 * 4 * 4];
+        draw_sprites_wasm(
+            &position,
+            &mut img,
+            4,
+            4,
+            &mut [f32::MAX; 4],
+            &[0.0; 4],
+            100,
+            0,
+            10.0,
+            0,
+            &textures,
+            &metas,
+            &mut found_sprites,
+            &mut WasmPickBuffer::new(),
+        );
+
+        // the middle of column 2 shows the wall texture
+        let pixel = &img[(2 * 4 + 2) * 4..][..3];
+        assert!(pixel[0] > pixel[1] && pixel[1] > pixel[2]);
+    }
+
+    /// `line_of_sight` with no doors opened and no walls being pushed.
+    fn sight(
+        from: (f32, f32),
+        to: (f32, f32),
+        cells: MapGrid,
+        materials: MapGrid,
+    ) -> Option<RayHit> {
+        line_of_sight(
+            from,
+            to,
+            cells,
+            materials,
+            &DoorStates::EMPTY,
+            &PushWalls::EMPTY,
+        )
+    }
+
+    #[test]
+    fn sees_through_windows_only() {
+        let map = AsciiMap::parse(
+            "
+            #######
+            #.W.|.#
+            #.#.D.#
+            #######
+            ",
+        )
+        .unwrap();
+        let sight = |from, to| sight(from, to, map.grid(), MapGrid::EMPTY);
+
+        assert_eq!(None, sight((1.5, 1.5), (3.5, 1.5)));
+        assert_eq!(None, sight((1.5, 1.5), (1.5, 2.5)));
+        assert_eq!(None, sight((3.5, 1.5), (3.6, 1.5)));
+
+        let wall = sight((3.5, 1.5), (5.5, 1.5)).unwrap();
+        assert_eq!((HitKind::Wall, 4, 1), (wall.kind, wall.map_x, wall.map_y));
+        let door = sight((5.5, 2.5), (3.5, 2.5)).unwrap();
+        assert_eq!((HitKind::Door, 4), (door.kind, door.map_x));
+        let solid = sight((1.5, 2.5), (3.5, 2.5)).unwrap();
+        assert_eq!((2, None), (solid.map_x, solid.segment));
+    }
+
+    #[test]
+    fn sees_over_low_walls() {
+        let map = AsciiMap::parse(
+            "
+            #####
+            #.|.#
+            #####
+            ",
+        )
+        .unwrap();
+        let mut materials = vec![0u64; map.cells.len()];
+        let mut low = CellMaterial::new();
+        low.set_wall_height(0, 0.5);
+        materials[map.width + 2] = low.to_u64();
+        let materials = MapGrid::Dense {
+            data: &materials,
+            width: map.width,
+            height: map.height,
+        };
+
+        let full = sight((1.5, 1.5), (3.5, 1.5), map.grid(), MapGrid::EMPTY);
+        assert_eq!(
+            Some((HitKind::Wall, 2)),
+            full.map(|hit| (hit.kind, hit.map_x))
+        );
+        assert_eq!(None, sight((1.5, 1.5), (3.5, 1.5), map.grid(), materials));
+    }
+
+    #[test]
+    fn sees_past_where_doors_are_open() {
+        let map = AsciiMap::parse(
+            "
+            ###
+            #.#
+            #D#
+            #.#
+            ###
+            ",
+        )
+        .unwrap();
+        let opened = |style, seconds| {
+            let mut doors = DoorStates::default();
+            doors.set_style(1, 2, style, DoorState::DEFAULT_SPEED);
+            doors.open(1, 2);
+            doors.update(seconds);
+            line_of_sight(
+                (1.25, 1.5),
+                (1.25, 3.5),
+                map.grid(),
+                MapGrid::EMPTY,
+                &doors,
+                &PushWalls::EMPTY,
+            )
+        };
+
+        let closed = sight((1.25, 1.5), (1.25, 3.5), map.grid(), MapGrid::EMPTY);
+        assert_eq!(Some(HitKind::Door), closed.map(|hit| hit.kind));
+
+        // the left half of the door is still in the doorway, showing the right half of the texture
+        let slid = opened(DoorStyle::SlideLeft, 0.5).unwrap();
+        assert_eq!((HitKind::Door, 1, 2), (slid.kind, slid.map_x, slid.map_y));
+        assert!((slid.y - 2.05).abs() < 1e-4 && (slid.u - 0.75).abs() < 1e-4);
+        assert_eq!(None, opened(DoorStyle::SlideRight, 0.5));
+        assert_eq!(None, opened(DoorStyle::SlideUp, 0.5));
+        // slid all the way out of the doorway, whichever way
+        assert_eq!(None, opened(DoorStyle::SlideLeft, 1.0));
+        assert_eq!(None, opened(DoorStyle::SlideRight, 1.0));
+
+        let swung = opened(DoorStyle::Swing, 0.5).unwrap();
+        assert!((swung.y - 2.3).abs() < 1e-4);
+        // at 45 degrees, facing back up the corridor
+        assert!((swung.normal_x - swung.normal_y.abs()).abs() < 1e-4 && swung.normal_y < 0.0);
+    }
+
+    #[test]
+    fn is_blocked_by_walls_being_pushed() {
+        let mut map = AsciiMap::parse(
+            "
+            ######
+            #.#..#
+            ######
+            ",
+        )
+        .unwrap();
+        let mut materials = vec![0u64; map.cells.len()];
+        materials[8] = 0x64;
+        let mut layers = WasmMapLayers::new(map.width, map.height);
+        layers.add_layer(map.cells.as_mut_ptr(), materials.as_mut_ptr(), 0.0);
+        assert!(layers.push_wall(0, 2, 1, Push::new(1, 0, 2, 1.0)));
+
+        layers.update_push_walls(0.5);
+        let in_the_way =
+            line_of_sight_js(1.5, 1.5, 4.5, 1.5, STANDING_EYE_HEIGHT, &layers).unwrap();
+        assert!((in_the_way.x - 2.5).abs() < 1e-4);
+        assert_eq!(100, in_the_way.texture);
+
+        layers.update_push_walls(1.5);
+        let pushed = line_of_sight_js(1.5, 1.5, 4.5, 1.5, STANDING_EYE_HEIGHT, &layers).unwrap();
+        assert_eq!((4, 100), (pushed.map_x, pushed.texture));
+    }
+}
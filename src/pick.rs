@@ -0,0 +1,230 @@
+//! What's on screen at a pixel, for the crosshair, mouse interaction and the level editor.
+//!
+//! `render` fills a `WasmPickBuffer` with the camera and the screen rectangles of the sprites it
+//! drew. Picking then re-casts the one column for walls, doors and windows of the layer the
+//! camera stands on and returns the nearest of the wall and the sprites under the pixel.
+//! Sprites are picked by their rectangle, including its transparent texels.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::camera::STANDING_EYE_HEIGHT;
+use crate::helpers::Position;
+use crate::hit::RayHit;
+use crate::layers::WasmMapLayers;
+use crate::{raycast_column, translate_coordinate_to_camera};
+
+// low walls and windows a pick looks past, when the pixel is above or below them
+const MAX_PICK_SURFACES: usize = 8;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct SpriteHit {
+    /// Position of the sprite in the sprite data passed to `populateFromArray`.
+    pub index: u32,
+    pub r#type: i32,
+    pub x: f32,
+    pub y: f32,
+    /// Straight line distance from the camera.
+    pub distance: f32,
+    #[serde(skip)]
+    pub left: i32, // screen rectangle, right and bottom exclusive
+    #[serde(skip)]
+    pub right: i32,
+    #[serde(skip)]
+    pub top: i32,
+    #[serde(skip)]
+    pub bottom: i32,
+    #[serde(skip)]
+    pub depth: f32, // in zbuffer units
+}
+
+/// Serialized with an `object` field of `"wall"` (doors and windows too, see `kind`) or
+/// `"sprite"`, next to the fields of the hit.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "object", rename_all = "lowercase")]
+pub enum Pick {
+    Wall(RayHit),
+    Sprite(SpriteHit),
+}
+
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmPickBuffer {
+    position: Option<Position>,
+    width: i32,
+    height: i32,
+    max_distance: f32,
+    sprites: Vec<SpriteHit>,
+}
+
+#[wasm_bindgen]
+impl WasmPickBuffer {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The nearest thing at pixel (x, y) of the last frame as `{ object: "wall" | "sprite", ... }`,
+    /// undefined for floor, ceiling and sky. Pass the layers the frame was rendered with.
+    #[wasm_bindgen(js_name = pick)]
+    pub fn pick_js(&self, x: i32, y: i32, layers: &WasmMapLayers) -> JsValue {
+        match self.pick(x, y, layers) {
+            Some(pick) => serde_wasm_bindgen::to_value(&pick).unwrap_or(JsValue::UNDEFINED),
+            None => JsValue::UNDEFINED,
+        }
+    }
+}
+
+// 🦀 Rust-only implementation block
+impl WasmPickBuffer {
+    /// Starts a new frame, forgetting the sprites of the last one.
+    pub fn begin_frame(&mut self, position: &Position, width: i32, height: i32, max_distance: f32) {
+        self.position = Some(*position);
+        self.width = width;
+        self.height = height;
+        self.max_distance = max_distance;
+        self.sprites.clear();
+    }
+
+    pub fn add_sprite(&mut self, sprite: SpriteHit) {
+        self.sprites.push(sprite);
+    }
+
+    pub fn pick(&self, x: i32, y: i32, layers: &WasmMapLayers) -> Option<Pick> {
+        let position = self.position?;
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let sprite = self
+            .sprites
+            .iter()
+            .filter(|sprite| {
+                (sprite.left..sprite.right).contains(&x) && (sprite.top..sprite.bottom).contains(&y)
+            })
+            .min_by(|a, b| a.depth.total_cmp(&b.depth));
+
+        match (self.pick_wall(x, y, &position, layers), sprite) {
+            (Some((wall, depth)), Some(sprite)) if depth <= sprite.depth => Some(Pick::Wall(wall)),
+            (_, Some(sprite)) => Some(Pick::Sprite(*sprite)),
+            (Some((wall, _)), None) => Some(Pick::Wall(wall)),
+            (None, None) => None,
+        }
+    }
+
+    /// The nearest wall at the pixel along with its depth, looking past low walls and windows the
+    /// pixel is above or below.
+    fn pick_wall(
+        &self,
+        x: i32,
+        y: i32,
+        position: &Position,
+        layers: &WasmMapLayers,
+    ) -> Option<(RayHit, f32)> {
+        let layer = layers.layer_at(position.z - STANDING_EYE_HEIGHT);
+        let cells = layers.cells(layer);
+        let materials = layers.materials(layer);
+        // as in `render`, the layer is drawn as if standing that much higher or lower
+        let camera = Position {
+            z: position.z - layers.floor_height(layer),
+            ..*position
+        };
+
+        let inv_det = (camera.plane_x * camera.dir_y - camera.dir_x * camera.plane_y).abs();
+
+        let surfaces = raycast_column(
+            x,
+            &camera,
+            cells,
+            materials,
+            layers.doors(layer),
+            layers.push_walls(layer),
+            layers.portals(layer),
+            self.width,
+            self.max_distance,
+            0,
+            None,
+            MAX_PICK_SURFACES,
+            false,
+            layers.tallest_wall(layer),
+            0,
+        )
+        .surfaces;
+
+        surfaces.into_iter().find_map(|hit| {
+            let projection = translate_coordinate_to_camera(
+                &camera,
+                hit.x - camera.x,
+                hit.y - camera.y,
+                hit.height,
+                self.width,
+                self.height,
+                inv_det,
+            );
+            let top = projection.screen_y_ceiling;
+            // raised doors show from the top of the doorway down to their bottom
+            let visible_height = (projection.full_height as f32 * (1.0 - hit.lift)) as i32;
+
+            (top..top + visible_height)
+                .contains(&y)
+                .then_some((hit, projection.distance))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii::AsciiMap;
+    use crate::hit::HitKind;
+
+    #[test]
+    fn picks_nearest_wall_or_sprite() {
+        let mut map = AsciiMap::parse(
+            "
+            ######
+            #..W.#
+            ######
+            ",
+        )
+        .unwrap();
+        let mut layers = WasmMapLayers::new(map.width, map.height);
+        layers.add_layer(map.cells.as_mut_ptr(), std::ptr::null_mut(), 0.0);
+
+        let position = Position {
+            plane_y: -0.66,
+            plane_y_initial: 0.66,
+            ..Position::looking_along(1.5, 1.5, 1.0, 0.0, 0.5)
+        };
+        let mut picks = WasmPickBuffer::new();
+        assert_eq!(None, picks.pick(50, 50, &layers));
+
+        picks.begin_frame(&position, 100, 100, 10.0);
+        let Some(Pick::Wall(window)) = picks.pick(50, 50, &layers) else {
+            panic!("expected a wall");
+        };
+        assert_eq!(
+            (HitKind::Window, 3, 1),
+            (window.kind, window.map_x, window.map_y)
+        );
+        assert!((window.x - 3.0).abs() < 1e-4);
+        // above the window, in the sky
+        assert_eq!(None, picks.pick(50, 0, &layers));
+
+        let sprite = SpriteHit {
+            index: 4,
+            r#type: 7,
+            x: 2.5,
+            y: 1.5,
+            distance: 1.0,
+            left: 40,
+            right: 60,
+            top: 30,
+            bottom: 70,
+            depth: 0.66,
+        };
+        picks.add_sprite(sprite);
+        assert_eq!(Some(Pick::Sprite(sprite)), picks.pick(50, 50, &layers));
+        assert!(matches!(picks.pick(30, 50, &layers), Some(Pick::Wall(_))));
+    }
+}
@@ -0,0 +1,248 @@
+//! Portals: wall segments linked to another segment of the same layer, anywhere in the map.
+//!
+//! A ray that runs into a portal goes on from the middle line of the linked segment, turned by
+//! the angle between the two, so the room behind the linked segment is seen through the portal;
+//! see `ViewTransform::through`. Walking into a portal moves the player the same way, see `walk`.
+//! Links go both ways and are kept per layer, keyed by cell and segment, next to the doors.
+
+use std::collections::BTreeMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::cell::{Cell, CellGeometry};
+use crate::grid::MapGrid;
+use crate::hit::RayHit;
+use crate::view::ViewTransform;
+
+/// One end of a portal, segment `segment` of cell (x, y), see `WasmMapLayers.linkPortal`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortalEnd {
+    pub x: i32,
+    pub y: i32,
+    pub segment: u8,
+}
+
+#[wasm_bindgen]
+impl PortalEnd {
+    #[wasm_bindgen(constructor)]
+    pub fn new(x: i32, y: i32, segment: u8) -> PortalEnd {
+        PortalEnd { x, y, segment }
+    }
+}
+
+impl From<PortalEnd> for (i32, i32, u8) {
+    fn from(end: PortalEnd) -> Self {
+        (end.x, end.y, end.segment)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortalLink {
+    pub x: i32, // the cell of the linked segment
+    pub y: i32,
+    pub segment: u8,
+    /// Whether one comes out of the linked segment on the side one went into the portal from,
+    /// i.e. turned around; otherwise on the other side, as through a doorway.
+    pub reversed: bool,
+}
+
+#[derive(Default)]
+pub struct Portals {
+    links: BTreeMap<(i32, i32, u8), PortalLink>,
+}
+
+impl Portals {
+    pub const EMPTY: Portals = Portals {
+        links: BTreeMap::new(),
+    };
+
+    #[inline(always)]
+    pub fn get(&self, x: i32, y: i32, segment: u8) -> Option<&PortalLink> {
+        if self.links.is_empty() {
+            return None;
+        }
+        self.links.get(&(x, y, segment))
+    }
+
+    /// Links the two segments both ways, replacing the links either of them had.
+    pub fn link(&mut self, from: (i32, i32, u8), to: (i32, i32, u8), reversed: bool) {
+        self.unlink(from);
+        self.unlink(to);
+        let (x, y, segment) = to;
+        self.links.insert(
+            from,
+            PortalLink {
+                x,
+                y,
+                segment,
+                reversed,
+            },
+        );
+        let (x, y, segment) = from;
+        self.links.insert(
+            to,
+            PortalLink {
+                x,
+                y,
+                segment,
+                reversed,
+            },
+        );
+    }
+
+    /// Removes the link of the segment, from both ends.
+    pub fn unlink(&mut self, (x, y, segment): (i32, i32, u8)) {
+        if let Some(link) = self.links.remove(&(x, y, segment)) {
+            self.links.remove(&(link.x, link.y, link.segment));
+        }
+    }
+
+    /// How things past the linked segment appear through the portal at segment `segment` of
+    /// cell (x, y), seen from `entry_distance`. None if it isn't linked or either segment can't
+    /// be a portal.
+    pub fn view(
+        &self,
+        cells: MapGrid,
+        x: i32,
+        y: i32,
+        segment: u8,
+        entry_distance: f32,
+    ) -> Option<ViewTransform> {
+        let link = self.get(x, y, segment)?;
+        let from = middle_line(cells, x, y, segment)?;
+        let (start, end) = middle_line(cells, link.x, link.y, link.segment)?;
+        let to = if link.reversed {
+            (end, start)
+        } else {
+            (start, end)
+        };
+
+        Some(ViewTransform::through(from, to, entry_distance))
+    }
+
+    /// For a ray from `origin` along `ray_dir` that ran into a portal: how things past the linked
+    /// segment appear through it, and how far along the ray it crosses the middle of the portal,
+    /// which is where it comes out of the middle of the linked segment.
+    pub fn enter(
+        &self,
+        cells: MapGrid,
+        hit: &RayHit,
+        origin: (f32, f32),
+        ray_dir: (f32, f32),
+    ) -> Option<(ViewTransform, f32)> {
+        let segment = hit.segment?;
+        let view = self.view(cells, hit.map_x, hit.map_y, segment, hit.distance)?;
+        let (start, end) = middle_line(cells, hit.map_x, hit.map_y, segment)?;
+        let (line_x, line_y) = (end.0 - start.0, end.1 - start.1);
+        let cross = ray_dir.0 * line_y - ray_dir.1 * line_x;
+        if cross == 0.0 {
+            return None;
+        }
+
+        let ray_length = ((start.0 - origin.0) * line_y - (start.1 - origin.1) * line_x) / cross;
+        Some((view, ray_length))
+    }
+}
+
+/// The line through the middle of segment `segment` of cell (x, y), start to end in map
+/// coordinates: north to south for east segments, west to east for north segments. None for
+/// pillars and cells without the segment.
+pub fn middle_line(
+    cells: MapGrid,
+    x: i32,
+    y: i32,
+    segment: u8,
+) -> Option<((f32, f32), (f32, f32))> {
+    let cell = Cell::from_u64(cells.get(x, y));
+    let segment = cell.walls().get(segment as usize)?;
+    let (x, y) = (x as f32, y as f32);
+    let unit = |nibble: u8| (nibble % 11) as f32 / 10.0;
+
+    match cell.geometry {
+        CellGeometry::Lines => {
+            let ((start_x, start_y), (end_x, end_y)) = segment.endpoints();
+            Some(((x + start_x, y + start_y), (x + end_x, y + end_y)))
+        }
+        CellGeometry::Cylinder => None,
+        _ => {
+            let across = unit(segment.offset) + unit(segment.thickness) / 2.0;
+            let from = unit(segment.offset_secondary);
+            let to = from + unit(segment.depth);
+            if segment.is_north {
+                Some(((x + from, y + across), (x + to, y + across)))
+            } else {
+                Some(((x + across, y + from), (x + across, y + to)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii::AsciiMap;
+    use crate::hit::HitKind;
+    use crate::{ColumnCast, TestRay};
+
+    #[test]
+    fn sees_through_linked_segments() {
+        // two thin walls, the one at (2, 1) leads to the one at (2, 3)
+        let map = AsciiMap::parse(
+            "
+            ######
+            #.|..#
+            ######
+            #.|..#
+            ######
+            ",
+        )
+        .unwrap();
+        let mut portals = Portals::default();
+        portals.link((2, 1, 0), (2, 3, 0), false);
+        assert_eq!(Some(1), portals.get(2, 3, 0).map(|link| link.y));
+
+        let cast = |portals: &Portals| {
+            TestRay {
+                portals,
+                max_surfaces: 8,
+                stop_at_first_hit: false,
+                max_bounces: 2,
+                ..TestRay::new(map.grid())
+            }
+            .cast(1.5, 1.5, 1.0, 0.0)
+        };
+
+        let ColumnCast {
+            perp_wall_dist,
+            ray_hit,
+            surfaces,
+            ..
+        } = cast(&portals);
+        let portal = ray_hit.unwrap();
+        assert_eq!(
+            (HitKind::Portal, 2, 1),
+            (portal.kind, portal.map_x, portal.map_y)
+        );
+        assert!((perp_wall_dist - portal.perp_distance).abs() < 1e-4);
+        // the wall at the end of the lower corridor, as far as if the corridors were one
+        let seen = surfaces.last().unwrap();
+        assert_eq!(
+            (HitKind::Wall, 5, 3, 1),
+            (seen.kind, seen.map_x, seen.map_y, seen.portals)
+        );
+        assert!((seen.perp_distance - 3.5).abs() < 1e-2);
+
+        // turned around, the upper corridor is seen again from the lower one
+        portals.link((2, 1, 0), (2, 3, 0), true);
+        let surfaces = cast(&portals).surfaces;
+        let seen = surfaces.last().unwrap();
+        assert_eq!((HitKind::Wall, 0, 3), (seen.kind, seen.map_x, seen.map_y));
+
+        let view = portals.view(map.grid(), 2, 1, 0, 1.0).unwrap();
+        let (x, y) = view.apply((1.5, 3.5));
+        assert!((x - 2.6).abs() < 1e-4 && (y - 1.5).abs() < 1e-4);
+        portals.unlink((2, 3, 0));
+        assert_eq!(None, portals.get(2, 1, 0));
+    }
+}
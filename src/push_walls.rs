@@ -0,0 +1,245 @@
+//! Push walls: solid cells that slide away when pushed, Wolfenstein style, usually to reveal a
+//! secret area.
+//!
+//! While it moves, the wall isn't in the map. The cell it left already holds what the cell it's
+//! headed to held, so the floor it uncovers matches the one it'll cover, and `raycast_column`
+//! traces the wall as a block at its current position. Once it arrives it's written into the map,
+//! see `WasmMapLayers::update_push_walls`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::cell::CellMaterial;
+use crate::helpers::TextureType;
+use crate::hit::{HitKind, RayHit};
+
+/// Which way a wall is pushed, how far and how fast, see `WasmMapLayers.pushWall`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Push {
+    pub dir_x: i32, // one of the four directions along the grid
+    pub dir_y: i32,
+    pub cells: i32, // how far it moves
+    pub speed: f32, // in cells per second
+}
+
+#[wasm_bindgen]
+impl Push {
+    #[wasm_bindgen(constructor)]
+    pub fn new(dir_x: i32, dir_y: i32, cells: i32, speed: f32) -> Push {
+        Push {
+            dir_x,
+            dir_y,
+            cells,
+            speed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PushWall {
+    pub x: i32, // the cell it started from
+    pub y: i32,
+    pub dir_x: i32, // one of the four directions along the grid
+    pub dir_y: i32,
+    pub cells: i32,  // how far it moves
+    pub speed: f32,  // in cells per second
+    pub offset: f32, // how far it has moved so far, in cells
+    pub cell: u64,
+    pub material: u64,
+}
+
+impl PushWall {
+    /// The north west corner of the block.
+    pub fn position(&self) -> (f32, f32) {
+        (
+            self.x as f32 + self.dir_x as f32 * self.offset,
+            self.y as f32 + self.dir_y as f32 * self.offset,
+        )
+    }
+
+    pub fn destination(&self) -> (i32, i32) {
+        (
+            self.x + self.dir_x * self.cells,
+            self.y + self.dir_y * self.cells,
+        )
+    }
+
+    /// The cells the wall passes through after the one it started from, ending with the
+    /// destination.
+    pub fn path(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        (1..=self.cells).map(|step| (self.x + self.dir_x * step, self.y + self.dir_y * step))
+    }
+
+    pub fn has_arrived(&self) -> bool {
+        self.offset >= self.cells as f32
+    }
+
+    /// Where a ray from `origin` along `ray_dir` enters the block, if that's in cell
+    /// (map_x, map_y). Nothing from inside of the block.
+    pub fn hit(
+        &self,
+        map_x: i32,
+        map_y: i32,
+        origin: (f32, f32),
+        ray_dir: (f32, f32),
+    ) -> Option<RayHit> {
+        let (left, top) = self.position();
+        if left >= (map_x + 1) as f32
+            || left + 1.0 <= map_x as f32
+            || top >= (map_y + 1) as f32
+            || top + 1.0 <= map_y as f32
+        {
+            return None;
+        }
+
+        // how far along the ray it's between the faces of either axis
+        let slab = |origin: f32, dir: f32, low: f32| {
+            if dir == 0.0 {
+                if (low..=low + 1.0).contains(&origin) {
+                    (f32::NEG_INFINITY, f32::INFINITY)
+                } else {
+                    (f32::INFINITY, f32::NEG_INFINITY)
+                }
+            } else {
+                let (near, far) = ((low - origin) / dir, (low + 1.0 - origin) / dir);
+                (near.min(far), near.max(far))
+            }
+        };
+        let (enter_x, exit_x) = slab(origin.0, ray_dir.0, left);
+        let (enter_y, exit_y) = slab(origin.1, ray_dir.1, top);
+        let ray_length = enter_x.max(enter_y);
+        if ray_length > exit_x.min(exit_y) || ray_length <= 0.0 {
+            return None;
+        }
+
+        let x = origin.0 + ray_dir.0 * ray_length;
+        let y = origin.1 + ray_dir.1 * ray_length;
+        // the rest of the block is hit from the cell it's in
+        if x < map_x as f32 || x > (map_x + 1) as f32 || y < map_y as f32 || y > (map_y + 1) as f32
+        {
+            return None;
+        }
+
+        let side = (enter_y > enter_x) as u8;
+        let (normal_x, normal_y, u) = if side == 0 {
+            (-ray_dir.0.signum(), 0.0, y - top)
+        } else {
+            (0.0, -ray_dir.1.signum(), x - left)
+        };
+        let texture = match CellMaterial::from_u64(self.material).wall_texture(0) {
+            0 => TextureType::WALL as i32,
+            texture => texture as i32,
+        };
+
+        Some(RayHit {
+            x,
+            y,
+            perp_distance: ray_length,
+            distance: (x - origin.0).hypot(y - origin.1),
+            map_x,
+            map_y,
+            segment: None,
+            side,
+            normal_x,
+            normal_y,
+            texture,
+            u: u.clamp(0.0, 1.0),
+            height: 1.0,
+            lift: 0.0,
+            kind: HitKind::Wall,
+            bounces: 0,
+            portals: 0,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct PushWalls {
+    moving: Vec<PushWall>,
+}
+
+impl PushWalls {
+    pub const EMPTY: PushWalls = PushWalls { moving: vec![] };
+
+    /// Whether a moving wall started from or is headed through cell (x, y).
+    pub fn is_taken(&self, x: i32, y: i32) -> bool {
+        self.moving
+            .iter()
+            .any(|wall| (wall.x, wall.y) == (x, y) || wall.path().any(|cell| cell == (x, y)))
+    }
+
+    pub fn start(&mut self, wall: PushWall) {
+        self.moving.push(wall);
+    }
+
+    /// The nearest of the walls moving through cell (x, y) a ray runs into.
+    #[inline(always)]
+    pub fn hit(
+        &self,
+        map_x: i32,
+        map_y: i32,
+        origin: (f32, f32),
+        ray_dir: (f32, f32),
+    ) -> Option<RayHit> {
+        if self.moving.is_empty() {
+            return None;
+        }
+
+        self.moving
+            .iter()
+            .filter_map(|wall| wall.hit(map_x, map_y, origin, ray_dir))
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+
+    /// Moves the walls along, returning the ones that arrived.
+    pub fn update(&mut self, seconds: f32) -> Vec<PushWall> {
+        for wall in self.moving.iter_mut() {
+            wall.offset = (wall.offset + wall.speed * seconds).min(wall.cells as f32);
+        }
+
+        self.moving
+            .extract_if(.., |wall| wall.has_arrived())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traces_walls_between_cells() {
+        let mut walls = PushWalls::default();
+        walls.start(PushWall {
+            x: 2,
+            y: 1,
+            dir_x: 1,
+            dir_y: 0,
+            cells: 2,
+            speed: 1.0,
+            offset: 0.0,
+            cell: 0x1,
+            material: 0,
+        });
+        walls.update(0.5);
+        assert!(walls.is_taken(4, 1) && !walls.is_taken(5, 1));
+
+        // half way into the next cell, seen from the west
+        assert_eq!(None, walls.hit(3, 1, (0.5, 1.25), (1.0, 0.0)));
+        let hit = walls.hit(2, 1, (0.5, 1.25), (1.0, 0.0)).unwrap();
+        assert!((hit.x - 2.5).abs() < 1e-4 && (hit.perp_distance - 2.0).abs() < 1e-4);
+        assert_eq!((0, -1.0, HitKind::Wall), (hit.side, hit.normal_x, hit.kind));
+        assert!((hit.u - 0.25).abs() < 1e-4);
+
+        assert!(walls.update(1.0).is_empty());
+        let arrived = walls.update(0.5);
+        assert_eq!(
+            vec![(4, 1)],
+            arrived
+                .iter()
+                .map(PushWall::destination)
+                .collect::<Vec<_>>()
+        );
+        assert!(!walls.is_taken(4, 1));
+    }
+}
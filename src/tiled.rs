@@ -0,0 +1,799 @@
+//! Imports maps made in the Tiled editor, exported as JSON (`.tmj`) or XML (`.tmx`).
+//!
+//! Tile layers are told apart by their class or name:
+//!
+//! ```text
+//! walls     any tile makes a solid wall, or the raw `cell` property of the tile (u64, may be hex)
+//! floors    sets the floor texture
+//! ceilings  sets the ceiling bit and texture
+//! roads     sets the road bit and the floor texture
+//! ```
+//!
+//! Other tile layers are ignored. The texture of a tile is its int `texture` property, 0 (the
+//! default texture) if it has none. Tiles of image collection tilesets with a `texture` also add
+//! a texture binding for their image. Embedded tilesets only, since external ones can't be
+//! resolved from the bytes of the map alone.
+//!
+//! Every object of an object layer becomes a sprite. Its type is the `type` property, the class
+//! of the object or the `texture` of its tile, its angle the rotation and its height the `height`
+//! property (in wall heights, 1 by default).
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use base64::Engine;
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+
+use crate::cell::{Cell, CellMaterial, MAX_WALL_SEGMENTS};
+use crate::helpers::TextureType;
+use crate::level::{map_size, Level, LevelError, TextureBinding};
+
+// the high bits of a gid flip and rotate the tile
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LayerRole {
+    Walls,
+    Floors,
+    Ceilings,
+    Roads,
+}
+
+impl LayerRole {
+    fn from_name(name: &str) -> Option<LayerRole> {
+        match name.trim().to_lowercase().as_str() {
+            "walls" | "wall" => Some(LayerRole::Walls),
+            "floors" | "floor" => Some(LayerRole::Floors),
+            "ceilings" | "ceiling" => Some(LayerRole::Ceilings),
+            "roads" | "road" => Some(LayerRole::Roads),
+            _ => None,
+        }
+    }
+}
+
+type Properties = HashMap<String, String>;
+
+#[derive(Default)]
+struct TileInfo {
+    properties: Properties,
+    image: Option<(String, i32, i32)>,
+}
+
+struct TileLayer {
+    name: String,
+    role: LayerRole,
+    gids: Vec<u32>,
+}
+
+#[derive(Default)]
+struct TiledObject {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    rotation: f32,
+    gid: u32,
+    class: String,
+    properties: Properties,
+}
+
+#[derive(Default)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    layers: Vec<TileLayer>,
+    objects: Vec<TiledObject>,
+    tiles: HashMap<u32, TileInfo>, // by gid
+}
+
+#[wasm_bindgen]
+impl Level {
+    /// Reads a Tiled map exported as JSON or TMX, telling them apart by the first character.
+    #[wasm_bindgen(js_name = fromTiled)]
+    pub fn from_tiled(bytes: &[u8]) -> Result<Level, LevelError> {
+        let text = std::str::from_utf8(bytes)
+            .map_err(|_| LevelError::unpositioned("the map isn't UTF-8 text".to_string()))?;
+        let text = text.trim_start_matches('\u{feff}');
+
+        let map = if text.trim_start().starts_with('<') {
+            parse_tmx(text)?
+        } else {
+            parse_json(text)?
+        };
+
+        map.into_level()
+    }
+}
+
+impl TiledMap {
+    fn into_level(self) -> Result<Level, LevelError> {
+        let size = map_size(self.width, self.height)?;
+        let mut grid = vec![0u64; size];
+        let mut materials = vec![0u64; size];
+        let no_tile = TileInfo::default();
+
+        for layer in &self.layers {
+            if layer.gids.len() != size {
+                return Err(LevelError::unpositioned(format!(
+                    "layer \"{}\" has {} tiles, expected {}x{}",
+                    layer.name,
+                    layer.gids.len(),
+                    self.width,
+                    self.height
+                )));
+            }
+
+            for (idx, gid) in layer.gids.iter().enumerate() {
+                let gid = gid & GID_MASK;
+                if gid == 0 {
+                    continue;
+                }
+                let tile = self.tiles.get(&gid).unwrap_or(&no_tile);
+                let texture: u8 = property(&tile.properties, "texture")?.unwrap_or(0);
+
+                let mut cell = Cell::from_u64(grid[idx]);
+                let mut material = CellMaterial::from_u64(materials[idx]);
+
+                match layer.role {
+                    LayerRole::Walls => {
+                        let mut wall = match tile.properties.get("cell") {
+                            Some(value) => Cell::from_u64(parse_u64(value).ok_or_else(|| {
+                                LevelError::unpositioned(format!(
+                                    "tile {} has an invalid cell value \"{}\"",
+                                    gid, value
+                                ))
+                            })?),
+                            None => {
+                                let mut wall = Cell::new();
+                                wall.has_wall = true;
+                                wall
+                            }
+                        };
+                        // the other layers may have been read first
+                        wall.has_ceiling |= cell.has_ceiling;
+                        wall.has_road |= cell.has_road;
+                        cell = wall;
+
+                        for index in 0..MAX_WALL_SEGMENTS {
+                            material.set_wall_texture(index, texture);
+                        }
+                    }
+                    LayerRole::Floors => {
+                        material.floor_texture = match texture {
+                            0 => TextureType::FLOOR as u8,
+                            texture => texture,
+                        };
+                    }
+                    LayerRole::Ceilings => {
+                        cell.has_ceiling = true;
+                        if texture != 0 {
+                            material.ceiling_texture = texture;
+                        }
+                    }
+                    LayerRole::Roads => {
+                        cell.has_road = true;
+                        if texture != 0 {
+                            material.floor_texture = texture;
+                        }
+                    }
+                }
+
+                grid[idx] = cell.to_u64();
+                materials[idx] = material.to_u64();
+            }
+        }
+
+        let mut sprite_data = vec![];
+        for object in &self.objects {
+            let tile = self.tiles.get(&(object.gid & GID_MASK));
+            let sprite_type = match property::<i32>(&object.properties, "type")? {
+                Some(sprite_type) => Some(sprite_type),
+                None => object.class.trim().parse().ok().or(match tile {
+                    Some(tile) => property(&tile.properties, "texture")?,
+                    None => None,
+                }),
+            };
+            let Some(sprite_type) = sprite_type else {
+                return Err(LevelError::unpositioned(format!(
+                    "object at ({}, {}) has no sprite type",
+                    object.x, object.y
+                )));
+            };
+            let height: f32 = property(&object.properties, "height")?.unwrap_or(1.0);
+
+            // tile objects are anchored at their bottom left corner
+            let (x, y) = if object.gid != 0 {
+                (
+                    object.x + object.width / 2.0,
+                    object.y - object.height / 2.0,
+                )
+            } else {
+                (object.x, object.y)
+            };
+
+            sprite_data.extend([
+                x / self.tile_width,
+                y / self.tile_height,
+                object.rotation,
+                height * 100.0,
+                sprite_type as f32,
+            ]);
+        }
+
+        let mut textures: Vec<TextureBinding> = vec![];
+        let mut gids: Vec<&u32> = self.tiles.keys().collect();
+        gids.sort();
+        for gid in gids {
+            let tile = &self.tiles[gid];
+            let (Some((src, width, height)), Some(id)) =
+                (&tile.image, property::<i32>(&tile.properties, "texture")?)
+            else {
+                continue;
+            };
+            if textures.iter().any(|binding| binding.id == id) {
+                continue;
+            }
+            textures.push(TextureBinding {
+                id,
+                src: src.clone(),
+                width: *width,
+                height: *height,
+                angles: property(&tile.properties, "angles")?.unwrap_or(1),
+            });
+        }
+
+        Ok(Level {
+            width: self.width,
+            height: self.height,
+            grid,
+            materials,
+            sprite_data,
+            textures,
+        })
+    }
+}
+
+fn property<T: FromStr>(properties: &Properties, name: &str) -> Result<Option<T>, LevelError> {
+    match properties.get(name) {
+        Some(value) => value.trim().parse().map(Some).map_err(|_| {
+            LevelError::unpositioned(format!("invalid {} property \"{}\"", name, value))
+        }),
+        None => Ok(None),
+    }
+}
+
+fn parse_u64(value: &str) -> Option<u64> {
+    let value = value.trim();
+    match value.strip_prefix("0x").or(value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// Decodes the tile data of a layer, as written for both formats. Compressed data inflating to
+/// more than `tiles` gids is refused rather than inflated.
+fn decode_gids(
+    data: &str,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+    tiles: usize,
+) -> Result<Vec<u32>, String> {
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(|gid| gid.trim())
+            .filter(|gid| !gid.is_empty())
+            .map(|gid| gid.parse().map_err(|_| format!("invalid tile \"{}\"", gid)))
+            .collect(),
+        Some("base64") => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(data.trim())
+                .map_err(|error| format!("invalid base64 data: {}", error))?;
+            let limit = tiles * 4;
+            let bytes = match compression {
+                None | Some("") => bytes,
+                Some("zlib") => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(
+                    &bytes, limit,
+                )
+                .map_err(|_| "invalid zlib data, or more tiles than the map has".to_string())?,
+                Some("gzip") => gunzip(&bytes, limit)
+                    .ok_or("invalid gzip data, or more tiles than the map has")?,
+                Some(other) => return Err(format!("unsupported compression \"{}\"", other)),
+            };
+            Ok(bytes
+                .as_chunks::<4>()
+                .0
+                .iter()
+                .map(|gid| u32::from_le_bytes(*gid))
+                .collect())
+        }
+        Some(other) => Err(format!("unsupported encoding \"{}\"", other)),
+        None => Err("missing encoding".to_string()),
+    }
+}
+
+// skips the gzip header, miniz only does the deflate stream; None past `limit` bytes too
+fn gunzip(bytes: &[u8], limit: usize) -> Option<Vec<u8>> {
+    const FHCRC: u8 = 2;
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+
+    if bytes.len() < 10 || bytes[0] != 0x1f || bytes[1] != 0x8b {
+        return None;
+    }
+    let flags = bytes[3];
+    let mut start = 10;
+
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([*bytes.get(start)?, *bytes.get(start + 1)?]) as usize;
+        start += 2 + len;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            start += bytes.get(start..)?.iter().position(|byte| *byte == 0)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        start += 2;
+    }
+
+    miniz_oxide::inflate::decompress_to_vec_with_limit(bytes.get(start..)?, limit).ok()
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    r#type: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    class: String,
+    data: Option<JsonData>,
+    encoding: Option<String>,
+    compression: Option<String>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>, // of group layers
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonData {
+    Gids(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    rotation: f32,
+    #[serde(default)]
+    gid: u32,
+    #[serde(default)]
+    r#type: String, // called class since Tiled 1.9
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    firstgid: u32,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+    image: Option<String>,
+    #[serde(default)]
+    imagewidth: i32,
+    #[serde(default)]
+    imageheight: i32,
+}
+
+fn json_properties(properties: &[JsonProperty]) -> Properties {
+    properties
+        .iter()
+        .map(|property| {
+            let value = match &property.value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            (property.name.clone(), value)
+        })
+        .collect()
+}
+
+fn parse_json(text: &str) -> Result<TiledMap, LevelError> {
+    let json: JsonMap = serde_json::from_str(text)?;
+    if json.infinite {
+        return Err(LevelError::unpositioned(
+            "infinite maps aren't supported".to_string(),
+        ));
+    }
+
+    let size = map_size(json.width, json.height)?;
+    let mut map = TiledMap {
+        width: json.width,
+        height: json.height,
+        tile_width: json.tilewidth,
+        tile_height: json.tileheight,
+        ..TiledMap::default()
+    };
+
+    for tileset in &json.tilesets {
+        for tile in &tileset.tiles {
+            let gid = tileset.firstgid.checked_add(tile.id).ok_or_else(|| {
+                LevelError::unpositioned(format!(
+                    "tile {} of the tileset at {} is past the last gid",
+                    tile.id, tileset.firstgid
+                ))
+            })?;
+            map.tiles.insert(
+                gid,
+                TileInfo {
+                    properties: json_properties(&tile.properties),
+                    image: tile
+                        .image
+                        .clone()
+                        .map(|src| (src, tile.imagewidth, tile.imageheight)),
+                },
+            );
+        }
+    }
+
+    let mut layers: Vec<&JsonLayer> = json.layers.iter().rev().collect();
+    while let Some(layer) = layers.pop() {
+        match layer.r#type.as_str() {
+            "group" => layers.extend(layer.layers.iter().rev()),
+            "objectgroup" => map
+                .objects
+                .extend(layer.objects.iter().map(|object| TiledObject {
+                    x: object.x,
+                    y: object.y,
+                    width: object.width,
+                    height: object.height,
+                    rotation: object.rotation,
+                    gid: object.gid,
+                    class: if object.class.is_empty() {
+                        object.r#type.clone()
+                    } else {
+                        object.class.clone()
+                    },
+                    properties: json_properties(&object.properties),
+                })),
+            "tilelayer" => {
+                let Some(role) =
+                    LayerRole::from_name(&layer.class).or(LayerRole::from_name(&layer.name))
+                else {
+                    continue;
+                };
+                let gids = match &layer.data {
+                    Some(JsonData::Gids(gids)) => gids.clone(),
+                    Some(JsonData::Encoded(data)) => decode_gids(
+                        data,
+                        layer.encoding.as_deref(),
+                        layer.compression.as_deref(),
+                        size,
+                    )
+                    .map_err(|message| {
+                        LevelError::unpositioned(format!("layer \"{}\": {}", layer.name, message))
+                    })?,
+                    None => vec![],
+                };
+                map.layers.push(TileLayer {
+                    name: layer.name.clone(),
+                    role,
+                    gids,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(map)
+}
+
+impl From<roxmltree::Error> for LevelError {
+    fn from(error: roxmltree::Error) -> LevelError {
+        let position = error.pos();
+        // the position is part of the message too, keep it in the fields only
+        let message = error.to_string();
+        let message = match message.rfind(" at ") {
+            Some(idx) => message[..idx].to_string(),
+            None => message,
+        };
+
+        LevelError::at(position.row as usize, position.col as usize, message)
+    }
+}
+
+fn parse_tmx(text: &str) -> Result<TiledMap, LevelError> {
+    let doc = roxmltree::Document::parse(text)?;
+    let root = doc.root_element();
+
+    let error_at = |node: roxmltree::Node, message: String| {
+        let position = doc.text_pos_at(node.range().start);
+        LevelError::at(position.row as usize, position.col as usize, message)
+    };
+    let attribute = |node: roxmltree::Node, name: &str| -> Result<Option<f32>, LevelError> {
+        node.attribute(name)
+            .map(|value| {
+                value.parse().map_err(|_| {
+                    error_at(node, format!("invalid {} attribute \"{}\"", name, value))
+                })
+            })
+            .transpose()
+    };
+    let required = |node: roxmltree::Node, name: &str| -> Result<f32, LevelError> {
+        attribute(node, name)?.ok_or_else(|| error_at(node, format!("missing {} attribute", name)))
+    };
+
+    if !root.has_tag_name("map") {
+        return Err(error_at(root, "expected a <map> element".to_string()));
+    }
+    if root.attribute("infinite") == Some("1") {
+        return Err(error_at(root, "infinite maps aren't supported".to_string()));
+    }
+
+    let mut map = TiledMap {
+        width: required(root, "width")? as usize,
+        height: required(root, "height")? as usize,
+        tile_width: required(root, "tilewidth")?,
+        tile_height: required(root, "tileheight")?,
+        ..TiledMap::default()
+    };
+    let size = map_size(map.width, map.height).map_err(|error| error_at(root, error.message()))?;
+
+    for tileset in root.children().filter(|node| node.has_tag_name("tileset")) {
+        let first_gid = required(tileset, "firstgid")? as u32;
+        for tile in tileset.children().filter(|node| node.has_tag_name("tile")) {
+            let image = tile
+                .children()
+                .find(|node| node.has_tag_name("image"))
+                .map(|image| -> Result<_, LevelError> {
+                    Ok((
+                        image.attribute("source").unwrap_or_default().to_string(),
+                        attribute(image, "width")?.unwrap_or(0.0) as i32,
+                        attribute(image, "height")?.unwrap_or(0.0) as i32,
+                    ))
+                })
+                .transpose()?;
+
+            let id = required(tile, "id")? as u32;
+            let gid = first_gid
+                .checked_add(id)
+                .ok_or_else(|| error_at(tile, format!("tile {} is past the last gid", id)))?;
+            map.tiles.insert(
+                gid,
+                TileInfo {
+                    properties: tmx_properties(tile),
+                    image,
+                },
+            );
+        }
+    }
+
+    let mut nodes: Vec<roxmltree::Node> = root.children().collect();
+    nodes.reverse();
+    while let Some(node) = nodes.pop() {
+        match node.tag_name().name() {
+            "group" => nodes.extend(node.children().collect::<Vec<_>>().into_iter().rev()),
+            "objectgroup" => {
+                for object in node.children().filter(|node| node.has_tag_name("object")) {
+                    map.objects.push(TiledObject {
+                        x: required(object, "x")?,
+                        y: required(object, "y")?,
+                        width: attribute(object, "width")?.unwrap_or(0.0),
+                        height: attribute(object, "height")?.unwrap_or(0.0),
+                        rotation: attribute(object, "rotation")?.unwrap_or(0.0),
+                        gid: object
+                            .attribute("gid")
+                            .and_then(|gid| gid.parse().ok())
+                            .unwrap_or(0),
+                        class: object
+                            .attribute("class")
+                            .or(object.attribute("type"))
+                            .unwrap_or_default()
+                            .to_string(),
+                        properties: tmx_properties(object),
+                    });
+                }
+            }
+            "layer" => {
+                let name = node.attribute("name").unwrap_or_default();
+                let Some(role) = LayerRole::from_name(node.attribute("class").unwrap_or_default())
+                    .or(LayerRole::from_name(name))
+                else {
+                    continue;
+                };
+                let Some(data) = node.children().find(|node| node.has_tag_name("data")) else {
+                    return Err(error_at(node, format!("layer \"{}\" has no data", name)));
+                };
+
+                let gids = match data.attribute("encoding") {
+                    // one <tile gid=".."/> per tile
+                    None => data
+                        .children()
+                        .filter(|node| node.has_tag_name("tile"))
+                        .map(|tile| Ok(attribute(tile, "gid")?.unwrap_or(0.0) as u32))
+                        .collect::<Result<Vec<u32>, LevelError>>()?,
+                    encoding => decode_gids(
+                        data.text().unwrap_or_default(),
+                        encoding,
+                        data.attribute("compression"),
+                        size,
+                    )
+                    .map_err(|message| error_at(data, message))?,
+                };
+
+                map.layers.push(TileLayer {
+                    name: name.to_string(),
+                    role,
+                    gids,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    Ok(map)
+}
+
+fn tmx_properties(node: roxmltree::Node) -> Properties {
+    node.children()
+        .filter(|node| node.has_tag_name("properties"))
+        .flat_map(|properties| properties.children())
+        .filter(|node| node.has_tag_name("property"))
+        .map(|property| {
+            // multi-line strings are the text of the element instead
+            let value = property
+                .attribute("value")
+                .or(property.text())
+                .unwrap_or_default();
+            (
+                property.attribute("name").unwrap_or_default().to_string(),
+                value.to_string(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON_MAP: &str = r#"{
+        "width": 2, "height": 2, "tilewidth": 32, "tileheight": 32, "infinite": false,
+        "layers": [
+            { "type": "tilelayer", "name": "Walls", "width": 2, "height": 2, "data": [1, 0, 0, 2] },
+            { "type": "group", "name": "ground", "layers": [
+                { "type": "tilelayer", "name": "roads", "width": 2, "height": 2,
+                  "encoding": "base64", "compression": "zlib", "data": "eJxjZGBgYIRiEAAAADQABA==" }
+            ] },
+            { "type": "objectgroup", "name": "sprites", "objects": [
+                { "x": 48, "y": 16, "rotation": 90, "type": "7",
+                  "properties": [{ "name": "height", "type": "float", "value": 0.5 }] }
+            ] }
+        ],
+        "tilesets": [{ "firstgid": 1, "tiles": [
+            { "id": 0, "image": "brick.png", "imagewidth": 64, "imageheight": 64,
+              "properties": [{ "name": "texture", "type": "int", "value": 100 }] },
+            { "id": 1, "properties": [{ "name": "cell", "type": "string", "value": "0x0A101041" }] }
+        ] }]
+    }"#;
+
+    const TMX_MAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="2" height="2" tilewidth="32" tileheight="32" infinite="0">
+ <tileset firstgid="1" name="walls" tilewidth="32" tileheight="32" tilecount="2">
+  <tile id="0">
+   <properties><property name="texture" type="int" value="100"/></properties>
+   <image source="brick.png" width="64" height="64"/>
+  </tile>
+  <tile id="1">
+   <properties><property name="cell" value="0x0A101041"/></properties>
+  </tile>
+ </tileset>
+ <layer id="1" name="Walls" width="2" height="2">
+  <data encoding="csv">1,0,
+0,2</data>
+ </layer>
+ <group id="2" name="ground">
+  <layer id="3" name="roads" width="2" height="2">
+   <data><tile gid="1"/><tile gid="1"/><tile gid="1"/><tile/></data>
+  </layer>
+ </group>
+ <objectgroup id="4" name="sprites">
+  <object id="1" type="7" x="48" y="16" rotation="90">
+   <properties><property name="height" type="float" value="0.5"/></properties>
+  </object>
+ </objectgroup>
+</map>"#;
+
+    fn assert_imported(level: &Level) {
+        assert_eq!((2, 2), (level.width, level.height));
+        assert_eq!(&[0x9, 0x8, 0x8, 0x0A101041], level.get_grid());
+        assert_eq!(
+            &[0x6464_6464, 0x6400_0000, 0x6400_0000, 0],
+            level.get_materials()
+        );
+        assert_eq!(vec![1.5, 0.5, 90.0, 50.0, 7.0], level.sprite_data());
+        assert_eq!(1, level.get_textures().len());
+        assert_eq!("brick.png", level.get_textures()[0].src);
+    }
+
+    #[test]
+    fn imports_json_maps() {
+        assert_imported(&Level::from_tiled(JSON_MAP.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn imports_tmx_maps() {
+        assert_imported(&Level::from_tiled(TMX_MAP.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn refuses_more_than_the_map_holds() {
+        let error = |text: &str| Level::from_tiled(text.as_bytes()).err().unwrap().message();
+
+        // the zlib layer inflates to four tiles
+        let small = JSON_MAP.replace(
+            r#""width": 2, "height": 2, "tilewidth""#,
+            r#""width": 1, "height": 1, "tilewidth""#,
+        );
+        assert!(error(&small).contains("more tiles than the map has"));
+        let huge = JSON_MAP.replace(
+            r#""width": 2, "height": 2, "tilewidth""#,
+            r#""width": 8192, "height": 8192, "tilewidth""#,
+        );
+        assert!(error(&huge).contains("too large"));
+        let past_last_gid = JSON_MAP.replace(r#""firstgid": 1"#, r#""firstgid": 4294967295"#);
+        assert!(error(&past_last_gid).contains("past the last gid"));
+        let past_last_gid = TMX_MAP.replace(r#"firstgid="1""#, r#"firstgid="4294967295""#);
+        assert!(error(&past_last_gid).contains("past the last gid"));
+    }
+
+    #[test]
+    fn reports_tmx_error_position() {
+        let error = Level::from_tiled(b"<map width=\"2\">\n  <layer name=\"walls\">\n</map>")
+            .err()
+            .unwrap();
+
+        assert_eq!(3, error.line);
+    }
+}
@@ -0,0 +1,316 @@
+//! Checks a u64 grid for cells the renderer would silently misinterpret.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::cell::{Cell, CellGeometry, WallSegment, MAX_WALL_SEGMENTS};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CellField {
+    Grid,
+    NumWalls,
+    Offset,
+    Thickness,
+    Depth,
+    OffsetSecondary,
+    Radius,
+    Door,
+    Geometry,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticKind {
+    /// The grid length doesn't match width * height.
+    GridSize,
+    /// A value the renderer would wrap around (nibbles above 10, more than 3 walls).
+    OutOfRange,
+    /// The segment sticks out of its cell.
+    OutOfCell,
+    /// Two parallel segments of the same cell overlap.
+    Overlap,
+    /// Segments 1 and 2 share the door bit, so a door on one is a door on both.
+    SharedDoorBit,
+    /// Geometry bits of a kind the renderer doesn't know, it draws aligned walls instead.
+    UnknownGeometry,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MapDiagnostic {
+    pub x: usize,
+    pub y: usize,
+    pub segment: Option<usize>,
+    pub field: CellField,
+    pub kind: DiagnosticKind,
+    pub message: String,
+}
+
+/// Returns an array of `{ x, y, segment, field, kind, message }` objects, empty if the map is valid.
+#[wasm_bindgen]
+pub fn validate_map(map_data: &[u64], map_width: usize, map_height: usize) -> JsValue {
+    let diagnostics = validate_grid(map_data, map_width, map_height);
+    serde_wasm_bindgen::to_value(&diagnostics).unwrap_or(JsValue::NULL)
+}
+
+pub fn validate_grid(map_data: &[u64], map_width: usize, map_height: usize) -> Vec<MapDiagnostic> {
+    let mut diagnostics = vec![];
+
+    if map_data.len() != map_width * map_height {
+        diagnostics.push(MapDiagnostic {
+            x: 0,
+            y: 0,
+            segment: None,
+            field: CellField::Grid,
+            kind: DiagnosticKind::GridSize,
+            message: format!(
+                "grid has {} cells, expected {}x{}",
+                map_data.len(),
+                map_width,
+                map_height
+            ),
+        });
+    }
+
+    for (idx, value) in map_data.iter().enumerate().take(map_width * map_height) {
+        validate_cell(*value, idx % map_width, idx / map_width, &mut diagnostics);
+    }
+
+    diagnostics
+}
+
+pub fn validate_cell(value: u64, x: usize, y: usize, diagnostics: &mut Vec<MapDiagnostic>) {
+    let cell = Cell::from_u64(value);
+    let mut push =
+        |segment: Option<usize>, field: CellField, kind: DiagnosticKind, message: String| {
+            diagnostics.push(MapDiagnostic {
+                x,
+                y,
+                segment,
+                field,
+                kind,
+                message,
+            })
+        };
+
+    if cell.num_walls as usize > MAX_WALL_SEGMENTS {
+        push(
+            None,
+            CellField::NumWalls,
+            DiagnosticKind::OutOfRange,
+            format!(
+                "{} walls set, at most {} are supported",
+                cell.num_walls, MAX_WALL_SEGMENTS
+            ),
+        );
+    }
+
+    if cell.has_unknown_geometry() {
+        push(
+            None,
+            CellField::Geometry,
+            DiagnosticKind::UnknownGeometry,
+            "unknown geometry, drawn as aligned walls".to_string(),
+        );
+    }
+
+    let walls = cell.walls();
+
+    for (index, segment) in walls.iter().enumerate() {
+        let nibbles = [
+            (CellField::Offset, segment.offset),
+            (CellField::Thickness, segment.thickness),
+            (CellField::Depth, segment.depth),
+            (CellField::OffsetSecondary, segment.offset_secondary),
+        ];
+        for (field, nibble) in nibbles {
+            if nibble > 10 {
+                push(
+                    Some(index),
+                    field,
+                    DiagnosticKind::OutOfRange,
+                    format!("{} tenths of a cell, at most 10 are supported", nibble),
+                );
+            }
+        }
+
+        match cell.geometry {
+            // the nibbles of walls at an angle are endpoints, which can't leave the cell
+            CellGeometry::Lines => continue,
+            CellGeometry::Cylinder => {
+                let radius = tenths(segment.depth);
+                let centre = [tenths(segment.offset), tenths(segment.thickness)];
+                if centre.iter().any(|c| *c < radius || c + radius > 10) {
+                    push(
+                        Some(index),
+                        CellField::Radius,
+                        DiagnosticKind::OutOfCell,
+                        "pillar reaches past the cell".to_string(),
+                    );
+                }
+                continue;
+            }
+            CellGeometry::Aligned => {}
+        }
+
+        if tenths(segment.offset) + tenths(segment.thickness) > 10 {
+            push(
+                Some(index),
+                CellField::Thickness,
+                DiagnosticKind::OutOfCell,
+                "offset and thickness reach past the cell".to_string(),
+            );
+        }
+        if tenths(segment.offset_secondary) + tenths(segment.depth) > 10 {
+            push(
+                Some(index),
+                CellField::Depth,
+                DiagnosticKind::OutOfCell,
+                "secondary offset and depth reach past the cell".to_string(),
+            );
+        }
+    }
+
+    // walls at an angle and pillars are free to cross each other
+    let overlap_walls = match cell.geometry {
+        CellGeometry::Aligned => walls,
+        CellGeometry::Lines | CellGeometry::Cylinder => &[],
+    };
+    for (index, segment) in overlap_walls.iter().enumerate() {
+        for (other_index, other) in overlap_walls.iter().enumerate().skip(index + 1) {
+            if overlaps(segment, other) {
+                push(
+                    Some(other_index),
+                    CellField::Offset,
+                    DiagnosticKind::Overlap,
+                    format!("overlaps segment {}", index),
+                );
+            }
+        }
+    }
+
+    if walls.len() == MAX_WALL_SEGMENTS && walls[1].is_door {
+        push(
+            Some(2),
+            CellField::Door,
+            DiagnosticKind::SharedDoorBit,
+            "segments 1 and 2 share the door bit, both are doors".to_string(),
+        );
+    }
+}
+
+// only parallel segments count, perpendicular ones meeting in a corner are fine
+fn overlaps(a: &WallSegment, b: &WallSegment) -> bool {
+    if a.is_north != b.is_north {
+        return false;
+    }
+
+    let across = |segment: &WallSegment| {
+        let start = tenths(segment.offset);
+        (start, start + tenths(segment.thickness))
+    };
+    let along = |segment: &WallSegment| {
+        let start = tenths(segment.offset_secondary);
+        (start, start + tenths(segment.depth))
+    };
+
+    let (a_start, a_end) = across(a);
+    let (b_start, b_end) = across(b);
+    // thin walls without thickness overlap when they're on the same line
+    let across_overlaps = a_start.max(b_start) < a_end.min(b_end)
+        || (a_start == b_start && (a_start == a_end || b_start == b_end));
+
+    let (a_start, a_end) = along(a);
+    let (b_start, b_end) = along(b);
+    let along_overlaps = a_start.max(b_start) < a_end.min(b_end);
+
+    across_overlaps && along_overlaps
+}
+
+// the value the renderer ends up using
+fn tenths(nibble: u8) -> u8 {
+    nibble % 11
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_corner_walls() {
+        assert!(validate_grid(&[0x00000A100A102087, 0x8], 2, 1).is_empty());
+    }
+
+    #[test]
+    fn reports_out_of_range_nibbles_and_walls() {
+        let diagnostics = validate_grid(&[0x000000000B101057, 0x5007], 1, 2);
+
+        assert_eq!(
+            vec![
+                (0, 0, Some(0), CellField::Depth, DiagnosticKind::OutOfRange),
+                (0, 1, None, CellField::NumWalls, DiagnosticKind::OutOfRange),
+            ],
+            diagnostics
+                .iter()
+                .map(|d| (d.x, d.y, d.segment, d.field, d.kind))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn reports_overlapping_segments() {
+        // two east walls at offset 0 and 0.1, both 0.2 thick
+        let diagnostics = validate_grid(&[0x00000A210A202007], 1, 1);
+
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(DiagnosticKind::Overlap, diagnostics[0].kind);
+        assert_eq!(Some(1), diagnostics[0].segment);
+    }
+
+    #[test]
+    fn reports_shared_door_bit() {
+        let diagnostics = validate_grid(&[0x0A100A100A1030D7], 1, 1);
+
+        assert!(diagnostics
+            .iter()
+            .any(|d| d.kind == DiagnosticKind::SharedDoorBit && d.segment == Some(2)));
+    }
+
+    #[test]
+    fn reports_unknown_geometry() {
+        let diagnostics = validate_grid(&[0x0000_0000_0A10_1C01], 1, 1);
+
+        assert_eq!(
+            vec![(None, CellField::Geometry, DiagnosticKind::UnknownGeometry)],
+            diagnostics
+                .iter()
+                .map(|d| (d.segment, d.field, d.kind))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn accepts_crossing_lines() {
+        // two diagonals of the same cell, stored as endpoints
+        assert!(validate_grid(&[0x0000_0AA0_AA00_2401], 1, 1).is_empty());
+    }
+
+    #[test]
+    fn reports_pillars_past_the_cell() {
+        // centred at (0.2, 0.5) with a radius of 0.3
+        let diagnostics = validate_grid(&[0x0000_0000_0352_1801], 1, 1);
+
+        assert_eq!(1, diagnostics.len());
+        assert_eq!(
+            (CellField::Radius, DiagnosticKind::OutOfCell),
+            (diagnostics[0].field, diagnostics[0].kind)
+        );
+    }
+
+    #[test]
+    fn reports_grid_size_mismatch() {
+        let diagnostics = validate_grid(&[0x8; 3], 2, 2);
+
+        assert_eq!(DiagnosticKind::GridSize, diagnostics[0].kind);
+    }
+}
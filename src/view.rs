@@ -0,0 +1,223 @@
+//! Where things seen in a mirror or through a portal appear to be.
+//!
+//! A ray that bounces off a mirror goes on from where it hit, and one that enters a portal from
+//! the segment it's linked to, so what it runs into afterwards is at its real position in the
+//! map. Seen from the camera it appears behind the mirror or the portal: reflected across the
+//! mirror line, or moved from the linked segment onto the portal, and likewise for every earlier
+//! mirror and portal on the way.
+
+/// Reflections across mirror lines and moves through portals, as `p -> matrix * p + offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewTransform {
+    matrix: [f32; 4], // row by row
+    offset: (f32, f32),
+    /// Mirrors the ray bounced off on the way.
+    pub bounces: u8,
+    /// Portals the ray went through on the way.
+    pub portals: u8,
+    /// Straight line distance from the camera to the first mirror or portal, things seen in it
+    /// are drawn right after it.
+    pub entry_distance: f32,
+}
+
+impl ViewTransform {
+    pub const IDENTITY: ViewTransform = ViewTransform {
+        matrix: [1.0, 0.0, 0.0, 1.0],
+        offset: (0.0, 0.0),
+        bounces: 0,
+        portals: 0,
+        entry_distance: 0.0,
+    };
+
+    /// Across the line through `point` with the unit `normal`.
+    pub fn across(point: (f32, f32), normal: (f32, f32), entry_distance: f32) -> ViewTransform {
+        let (nx, ny) = normal;
+        let along_normal = 2.0 * (point.0 * nx + point.1 * ny);
+
+        ViewTransform {
+            matrix: [
+                1.0 - 2.0 * nx * nx,
+                -2.0 * nx * ny,
+                -2.0 * nx * ny,
+                1.0 - 2.0 * ny * ny,
+            ],
+            offset: (along_normal * nx, along_normal * ny),
+            bounces: 1,
+            portals: 0,
+            entry_distance,
+        }
+    }
+
+    /// Through a portal along the line `from`, linked to the line `to`, both given start to
+    /// end: what's past `to` appears past `from`, as if `to` was turned and moved onto it.
+    pub fn through(
+        from: ((f32, f32), (f32, f32)),
+        to: ((f32, f32), (f32, f32)),
+        entry_distance: f32,
+    ) -> ViewTransform {
+        let angle = (from.1 .1 - from.0 .1).atan2(from.1 .0 - from.0 .0)
+            - (to.1 .1 - to.0 .1).atan2(to.1 .0 - to.0 .0);
+        let (sin, cos) = angle.sin_cos();
+        let turned = ViewTransform {
+            matrix: [cos, -sin, sin, cos],
+            offset: (0.0, 0.0),
+            bounces: 0,
+            portals: 1,
+            entry_distance,
+        };
+        let (x, y) = turned.apply(to.0);
+
+        ViewTransform {
+            offset: (from.0 .0 - x, from.0 .1 - y),
+            ..turned
+        }
+    }
+
+    /// This transform applied after `inner`, which came from a later bounce or portal.
+    pub fn after(&self, inner: &ViewTransform) -> ViewTransform {
+        let [a, b, c, d] = self.matrix;
+        let [e, f, g, h] = inner.matrix;
+        let (x, y) = self.apply(inner.offset);
+
+        ViewTransform {
+            matrix: [a * e + b * g, a * f + b * h, c * e + d * g, c * f + d * h],
+            offset: (x, y),
+            bounces: self.bounces + inner.bounces,
+            portals: self.portals + inner.portals,
+            entry_distance: self.entry_distance,
+        }
+    }
+
+    #[inline(always)]
+    pub fn apply(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let [a, b, c, d] = self.matrix;
+        (a * x + b * y + self.offset.0, c * x + d * y + self.offset.1)
+    }
+
+    /// The transform that undoes this one.
+    pub fn inverse(&self) -> ViewTransform {
+        let [a, b, c, d] = self.matrix;
+        let det = a * d - b * c;
+        let inverse = ViewTransform {
+            matrix: [d / det, -b / det, -c / det, a / det],
+            offset: (0.0, 0.0),
+            ..*self
+        };
+        let (x, y) = inverse.turn(self.offset);
+
+        ViewTransform {
+            offset: (-x, -y),
+            ..inverse
+        }
+    }
+
+    /// Turns a direction, which isn't moved by the offset.
+    #[inline(always)]
+    pub fn turn(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let [a, b, c, d] = self.matrix;
+        (a * x + b * y, c * x + d * y)
+    }
+
+    /// Whether anything is seen in a mirror or through a portal this way.
+    pub fn is_seen_through(&self) -> bool {
+        self.bounces > 0 || self.portals > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::ascii::AsciiMap;
+    use crate::grid::MapGrid;
+    use crate::hit::{HitKind, RayHit};
+    use crate::{ColumnCast, TestRay};
+
+    const MIRROR: u64 = 1 << 52;
+
+    #[test]
+    fn reflects_across_mirror_lines() {
+        // a mirror along x = 3, and one along y = 1 seen in it
+        let east = ViewTransform::across((3.0, 0.5), (-1.0, 0.0), 1.5);
+        let north = ViewTransform::across((4.0, 1.0), (0.0, 1.0), 2.0);
+        assert_eq!((5.0, 2.0), east.apply((1.0, 2.0)));
+
+        let both = east.after(&north);
+        assert_eq!((4.0, 0.0), both.apply((2.0, 2.0)));
+        assert_eq!((2, 1.5), (both.bounces, both.entry_distance));
+        assert_eq!((1.0, 2.0), ViewTransform::IDENTITY.apply((1.0, 2.0)));
+    }
+
+    #[test]
+    fn bounces_rays_off_mirrors() {
+        let map = AsciiMap::parse(
+            "
+            #####
+            #...#
+            #####
+            ",
+        )
+        .unwrap();
+        let mut materials = vec![0; map.cells.len()];
+        materials[9] = MIRROR;
+        let sprites = HashMap::from([((2, 1), vec![])]);
+        let cast = |materials: &[u64], max_bounces| {
+            TestRay {
+                materials: MapGrid::Dense {
+                    data: materials,
+                    width: map.width,
+                    height: map.height,
+                },
+                sprites_map: Some(&sprites),
+                max_surfaces: 8,
+                stop_at_first_hit: false,
+                max_bounces,
+                ..TestRay::new(map.grid())
+            }
+            .cast(1.5, 1.5, 1.0, 0.0)
+        };
+        let seen = |surfaces: &[RayHit]| -> Vec<(HitKind, i32, u8)> {
+            surfaces
+                .iter()
+                .map(|hit| (hit.kind, hit.map_x, hit.bounces))
+                .collect()
+        };
+
+        let ColumnCast {
+            ray_hit, surfaces, ..
+        } = cast(&materials, 0);
+        assert_eq!(Some(HitKind::Mirror), ray_hit.map(|hit| hit.kind));
+        assert_eq!(vec![(HitKind::Mirror, 4, 0)], seen(&surfaces));
+
+        // the zbuffer stays at the mirror, the wall behind the camera is seen in it
+        let ColumnCast {
+            perp_wall_dist,
+            met_coords,
+            surfaces,
+            ..
+        } = cast(&materials, 1);
+        assert!((perp_wall_dist - 2.5).abs() < 1e-4);
+        assert_eq!(
+            vec![(HitKind::Mirror, 4, 0), (HitKind::Wall, 0, 1)],
+            seen(&surfaces)
+        );
+        assert!((surfaces[1].perp_distance - 5.5).abs() < 1e-2);
+        let (_, _, reflection) = met_coords
+            .iter()
+            .find(|coord| coord.2.bounces == 1)
+            .unwrap();
+        assert_eq!((2, 1), (met_coords[0].0, met_coords[0].1));
+        let (x, y) = reflection.apply((2.5, 1.5));
+        assert!((x - 5.5).abs() < 1e-2 && y == 1.5);
+
+        // between two mirrors, until the bounces run out
+        materials[5] = MIRROR;
+        let surfaces = cast(&materials, 2).surfaces;
+        assert_eq!(
+            vec![(HitKind::Mirror, 4, 0), (HitKind::Mirror, 4, 2)],
+            seen(&surfaces)
+        );
+        assert!((surfaces[1].perp_distance - 8.5).abs() < 1e-2);
+    }
+}